@@ -0,0 +1,68 @@
+// Helpers shared by the bellman circuits
+
+// Domain tags prepended to sha256 preimages, one per circuit and use, so a
+// hash produced by one circuit is never a valid hash in another
+pub const MIXER_NOTE_DOMAIN: [u8; 16] = *b"mixer/note/hash/";
+pub const MIXER_FOLD_DOMAIN: [u8; 16] = *b"mixer/recursive/";
+pub const MIXER_AGGREGATE_DOMAIN: [u8; 16] = *b"mixer/aggregate/";
+pub const TWIN_NOTE_DOMAIN: [u8; 16] = *b"twin/note/sha256";
+
+/// Bits of `n` in the order the circuits feed them to sha256: big-endian
+/// bytes, and within each byte the most significant bit first. This is the
+/// bit string of `n.to_be_bytes()`, so hashing these bits in-circuit matches
+/// hashing the bytes natively.
+pub fn u128_to_circuit_bits(n: u128) -> [bool; 128] {
+    let mut bits = [false; 128];
+    for (i, byte) in n.to_be_bytes().iter().enumerate() {
+        for j in 0..8 {
+            bits[8 * i + j] = (byte >> (7 - j)) & 1u8 == 1u8;
+        }
+    }
+
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn combine(amount: u128, nonce: u128) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&amount.to_be_bytes());
+        bytes[16..].copy_from_slice(&nonce.to_be_bytes());
+
+        bytes
+    }
+
+    #[test]
+    fn bits_match_combined_preimage() {
+        for &(amount, nonce) in &[(0, 0), (1, 2), (2, 10), (u128::MAX, 1 << 127)] {
+            let preimage_bits = combine(amount, nonce)
+                .iter()
+                .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1u8 == 1u8))
+                .collect::<Vec<_>>();
+
+            let mut circuit_bits = u128_to_circuit_bits(amount).to_vec();
+            circuit_bits.extend(&u128_to_circuit_bits(nonce));
+            assert_eq!(circuit_bits, preimage_bits);
+        }
+
+        let bits = u128_to_circuit_bits(1);
+        assert!(bits[127] && bits[..127].iter().all(|b| !b));
+    }
+
+    #[test]
+    fn domains_are_distinct() {
+        let domains = [
+            MIXER_NOTE_DOMAIN,
+            MIXER_FOLD_DOMAIN,
+            MIXER_AGGREGATE_DOMAIN,
+            TWIN_NOTE_DOMAIN,
+        ];
+        for i in 0..domains.len() {
+            for j in i + 1..domains.len() {
+                assert_ne!(domains[i], domains[j]);
+            }
+        }
+    }
+}
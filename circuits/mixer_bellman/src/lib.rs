@@ -1,107 +1,784 @@
 use bellman::{
     gadgets::{
+        blake2s::blake2s,
         boolean::{AllocatedBit, Boolean},
         multipack,
         sha256::sha256,
     },
-    groth16, Circuit, ConstraintSystem, SynthesisError,
+    groth16, Circuit, ConstraintSystem, Index, LinearCombination, SynthesisError, Variable,
 };
-use pairing::bls12_381::Bls12;
-use pairing::Engine;
-use rand::rngs::OsRng;
+use ff::{Field, PrimeField};
+use gadgets::{u128_to_circuit_bits, MIXER_AGGREGATE_DOMAIN, MIXER_FOLD_DOMAIN, MIXER_NOTE_DOMAIN};
+use group::{CurveAffine, CurveProjective, EncodedPoint};
+use pairing::bls12_381::{Bls12, Fq12, Fr, G1Affine, G2Affine, G1};
+use pairing::{Engine, PairingCurveAffine};
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use subtle::Choice;
 
-fn convert_to_bits(num: u128) -> Vec<bool> {
-    num.to_be_bytes()
-        .into_iter()
-        .map(|byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8).rev())
-        .flatten()
+#[cfg(feature = "json")]
+use ff::PrimeFieldRepr;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
+
+pub mod verify_core;
+
+// Appended to a note preimage so its nullifier never equals its note hash
+const NULLIFIER_DOMAIN: [u8; 16] = *b"mixer/nullifier/";
+
+// Value limbs, nonce and blinding, 128 bits each
+const NOTE_PREIMAGE_BITS: usize = 512;
+// SHA256 appends a 1 bit and the 64-bit length to the domain tag and preimage,
+// then pads to whole 512-bit blocks. The native and circuit hashes only agree
+// while both see the same number of blocks.
+const NOTE_HASH_BLOCKS: usize = (128 + NOTE_PREIMAGE_BITS + 1 + 64).div_ceil(512);
+const _: () = assert!(NOTE_HASH_BLOCKS == 2);
+
+// Up to 255 notes a side, so the low limb sums of inputs and outputs differ
+// by less than 2^8 multiples of 2^128
+const CARRY_BITS: usize = 9;
+const CARRY_OFFSET: u128 = 1 << (CARRY_BITS - 1);
+
+// Big-endian bits, most significant first, as laid out by `u128_to_circuit_bits`
+fn bits_to_lc<E: Engine>(one: Variable, bits: &[Boolean]) -> LinearCombination<E> {
+    let mut coeff = E::Fr::one();
+    let mut lc = LinearCombination::zero();
+    for bit in bits.iter().rev() {
+        lc = lc + &bit.lc(one, coeff);
+        coeff.double();
+    }
+
+    lc
+}
+
+fn pow2<E: Engine>(exponent: usize) -> E::Fr {
+    let mut power = E::Fr::one();
+    for _ in 0..exponent {
+        power.double();
+    }
+
+    power
+}
+
+fn alloc_bits<E: Engine, CS: ConstraintSystem<E>>(
+    mut cs: CS,
+    bits: &[bool],
+) -> Result<Vec<Boolean>, SynthesisError> {
+    bits.iter()
+        .enumerate()
+        .map(|(i, b)| AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), Some(*b)))
+        .map(|b| b.map(Boolean::from))
+        .collect()
+}
+
+// bellman's blake2s gadget reads and writes each byte least significant bit
+// first, the reverse of the sha256 gadget and `multipack::bytes_to_bits`
+fn reverse_byte_bits(bits: &[Boolean]) -> Vec<Boolean> {
+    bits.chunks(8)
+        .flat_map(|byte| byte.iter().rev().cloned())
         .collect()
 }
 
-#[derive(Debug, Clone, Copy)]
+const BLAKE2S_PERSONALIZATION: [u8; 8] = *b"42zkmixr";
+
+// Hash used for note hashes and the recursive hash. Blake2s costs fewer
+// constraints. Nullifiers and the shaped hash stay SHA256 whatever the
+// choice, so a note has one nullifier however it is spent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum HashFunction {
+    #[default]
+    Sha256,
+    Blake2s,
+}
+
+impl HashFunction {
+    fn digest(self, preimage: &[u8]) -> Vec<u8> {
+        match self {
+            HashFunction::Sha256 => Sha256::digest(preimage).to_vec(),
+            HashFunction::Blake2s => blake2s_simd::Params::new()
+                .hash_length(32)
+                .personal(&BLAKE2S_PERSONALIZATION)
+                .hash(preimage)
+                .as_bytes()
+                .to_vec(),
+        }
+    }
+
+    fn hash_bits<E: Engine, CS: ConstraintSystem<E>>(
+        self,
+        cs: CS,
+        bits: &[Boolean],
+    ) -> Result<Vec<Boolean>, SynthesisError> {
+        match self {
+            HashFunction::Sha256 => sha256(cs, bits),
+            HashFunction::Blake2s => {
+                let hash = blake2s(cs, &reverse_byte_bits(bits), &BLAKE2S_PERSONALIZATION)?;
+                Ok(reverse_byte_bits(&hash))
+            }
+        }
+    }
+
+    fn to_byte(self) -> u8 {
+        match self {
+            HashFunction::Sha256 => 0,
+            HashFunction::Blake2s => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(HashFunction::Sha256),
+            1 => Some(HashFunction::Blake2s),
+            _ => None,
+        }
+    }
+}
+
+// Amount wider than u128, as a high and a low limb, high limb first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct U256 {
+    pub hi: u128,
+    pub lo: u128,
+}
+
+impl U256 {
+    pub fn checked_add(self, other: U256) -> Option<U256> {
+        let (lo, carry) = self.lo.overflowing_add(other.lo);
+        let hi = self.hi.checked_add(other.hi)?.checked_add(carry as u128)?;
+
+        Some(U256 { hi, lo })
+    }
+
+    pub fn to_be_bytes(&self) -> [u8; 32] {
+        let mut bytes = [0u8; 32];
+        bytes[..16].copy_from_slice(&self.hi.to_be_bytes());
+        bytes[16..].copy_from_slice(&self.lo.to_be_bytes());
+        bytes
+    }
+}
+
+impl From<u128> for U256 {
+    fn from(lo: u128) -> Self {
+        U256 { hi: 0, lo }
+    }
+}
+
+// None if the total doesn't fit in 256 bits
+fn sum_values<I: IntoIterator<Item = U256>>(values: I) -> Option<U256> {
+    values
+        .into_iter()
+        .try_fold(U256::default(), |acc, value| acc.checked_add(value))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Value(pub u128);
+
+impl From<u128> for Value {
+    fn from(value: u128) -> Self {
+        Value(value)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nonce(pub u128);
+
+impl From<u128> for Nonce {
+    fn from(nonce: u128) -> Self {
+        Nonce(nonce)
+    }
+}
+
+// Hides the value independently of the nonce, which only has to be unique
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Blinding(pub u128);
+
+impl From<u128> for Blinding {
+    fn from(blinding: u128) -> Self {
+        Blinding(blinding)
+    }
+}
+
+// JSON numbers can't carry a full u128, so encode them as decimal strings
+#[cfg(feature = "serde")]
+mod u128_string {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &u128, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u128, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(D::Error::custom)
+    }
+}
+
+// Public bounds every output nonce must fall within, inclusive. A verifier
+// can derive them from recent blocks to limit how long a prepared note stays
+// usable. The default admits any nonce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct NonceRange {
+    #[cfg_attr(feature = "serde", serde(with = "u128_string"))]
+    pub min: u128,
+    #[cfg_attr(feature = "serde", serde(with = "u128_string"))]
+    pub max: u128,
+}
+
+impl Default for NonceRange {
+    fn default() -> Self {
+        NonceRange {
+            min: 0,
+            max: u128::MAX,
+        }
+    }
+}
+
+impl NonceRange {
+    pub fn contains(&self, nonce: u128) -> bool {
+        self.min <= nonce && nonce <= self.max
+    }
+}
+
+// Ordered by value first, then nonce, then blinding. The value is split into
+// a high and a low limb, `value` being the low one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
 pub struct Amount {
+    #[cfg_attr(feature = "serde", serde(default, with = "u128_string"))]
+    pub value_hi: u128,
+    #[cfg_attr(feature = "serde", serde(with = "u128_string"))]
     pub value: u128,
+    #[cfg_attr(feature = "serde", serde(with = "u128_string"))]
     pub nonce: u128,
+    #[cfg_attr(feature = "serde", serde(default, with = "u128_string"))]
+    pub blinding: u128,
 }
 
 impl Amount {
-    pub fn new(value: u128, nonce: u128) -> Self {
-        Amount { value, nonce }
+    // Unblinded, use `with_blinding` to hide the value behind more than the
+    // nonce
+    pub fn new(value: Value, nonce: Nonce) -> Self {
+        Amount {
+            value_hi: 0,
+            value: value.0,
+            nonce: nonce.0,
+            blinding: 0,
+        }
     }
 
-    pub fn hash<E: Engine, CS: ConstraintSystem<E>>(
+    pub fn new_u256(value: U256, nonce: Nonce) -> Self {
+        Amount {
+            value_hi: value.hi,
+            ..Amount::new(Value(value.lo), nonce)
+        }
+    }
+
+    pub fn value_u256(&self) -> U256 {
+        U256 {
+            hi: self.value_hi,
+            lo: self.value,
+        }
+    }
+
+    pub fn with_blinding(mut self, blinding: Blinding) -> Self {
+        self.blinding = blinding.0;
+        self
+    }
+
+    // Same big-endian layout as the preimage hashed by `Amount::hash`
+    fn to_bytes(self) -> [u8; NOTE_PREIMAGE_BITS / 8] {
+        let mut bytes = [0u8; NOTE_PREIMAGE_BITS / 8];
+        bytes[..32].copy_from_slice(&self.value_u256().to_be_bytes());
+        bytes[32..48].copy_from_slice(&self.nonce.to_be_bytes());
+        bytes[48..].copy_from_slice(&self.blinding.to_be_bytes());
+        bytes
+    }
+
+    #[cfg(test)]
+    fn note_hash(&self) -> Vec<u8> {
+        self.note_hash_with(HashFunction::Sha256)
+    }
+
+    // Native counterpart of `Amount::hash`
+    fn note_hash_with(&self, hash: HashFunction) -> Vec<u8> {
+        let mut preimage = MIXER_NOTE_DOMAIN.to_vec();
+        preimage.extend(&self.to_bytes());
+
+        hash.digest(&preimage)
+    }
+
+    pub fn nullifier(&self) -> Vec<u8> {
+        let mut preimage = self.to_bytes().to_vec();
+        preimage.extend(&NULLIFIER_DOMAIN);
+
+        Sha256::digest(&preimage).to_vec()
+    }
+
+    fn alloc_preimage<E: Engine, CS: ConstraintSystem<E>>(
         &self,
         mut cs: CS,
     ) -> Result<Vec<Boolean>, SynthesisError> {
-        let amount_bits = convert_to_bits(self.value);
-        let nonce_bits = convert_to_bits(self.nonce);
+        let value_hi_bits = u128_to_circuit_bits(self.value_hi);
+        let value_lo_bits = u128_to_circuit_bits(self.value);
+        let nonce_bits = u128_to_circuit_bits(self.nonce);
+        let blinding_bits = u128_to_circuit_bits(self.blinding);
 
-        let mut preimage = [false; 256];
-        for i in 0..128 {
-            preimage[i] = amount_bits[i];
-            preimage[i + 128] = nonce_bits[i];
-        }
+        // With the domain tag the note hash spans two compression blocks
+        let mut preimage = [false; NOTE_PREIMAGE_BITS];
+        preimage[..128].copy_from_slice(&value_hi_bits);
+        preimage[128..256].copy_from_slice(&value_lo_bits);
+        preimage[256..384].copy_from_slice(&nonce_bits);
+        preimage[384..].copy_from_slice(&blinding_bits);
 
-        let preimage_bits = preimage
-            .into_iter()
+        preimage
+            .iter()
             .enumerate()
             .map(|(i, b)| {
                 AllocatedBit::alloc(cs.namespace(|| format!("preimage bits {}", i)), Some(*b))
             })
             .map(|b| b.map(Boolean::from))
-            .collect::<Result<Vec<_>, _>>()?;
+            .collect::<Result<Vec<_>, _>>()
+    }
+
+    pub fn hash<E: Engine, CS: ConstraintSystem<E>>(
+        &self,
+        mut cs: CS,
+        hash: HashFunction,
+    ) -> Result<Vec<Boolean>, SynthesisError> {
+        let preimage_bits = self.alloc_preimage(&mut cs)?;
+
+        hash_preimage(&mut cs, &preimage_bits, hash)
+    }
+
+    // Note hash and nullifier computed over the same allocated preimage
+    pub fn hash_with_nullifier<E: Engine, CS: ConstraintSystem<E>>(
+        &self,
+        mut cs: CS,
+        hash: HashFunction,
+    ) -> Result<(Vec<Boolean>, Vec<Boolean>), SynthesisError> {
+        let preimage_bits = self.alloc_preimage(&mut cs)?;
+        let hash = hash_preimage(&mut cs, &preimage_bits, hash)?;
+        let nullifier = nullify_preimage(&mut cs, &preimage_bits)?;
+
+        Ok((hash, nullifier))
+    }
+}
+
+fn constant_bits(bytes: &[u8]) -> Vec<Boolean> {
+    multipack::bytes_to_bits(bytes)
+        .into_iter()
+        .map(Boolean::constant)
+        .collect()
+}
+
+fn hash_preimage<E: Engine, CS: ConstraintSystem<E>>(
+    mut cs: CS,
+    preimage_bits: &[Boolean],
+    hash: HashFunction,
+) -> Result<Vec<Boolean>, SynthesisError> {
+    assert_eq!(preimage_bits.len(), NOTE_PREIMAGE_BITS);
+
+    let mut tagged_preimage = constant_bits(&MIXER_NOTE_DOMAIN);
+    tagged_preimage.extend(preimage_bits.iter().cloned());
 
-        sha256(cs.namespace(|| "sha256(amount + nonce)"), &preimage_bits)
+    hash.hash_bits(
+        cs.namespace(|| "hash(domain + amount + nonce + blinding)"),
+        &tagged_preimage,
+    )
+}
+
+fn nullify_preimage<E: Engine, CS: ConstraintSystem<E>>(
+    mut cs: CS,
+    preimage_bits: &[Boolean],
+) -> Result<Vec<Boolean>, SynthesisError> {
+    assert_eq!(preimage_bits.len(), NOTE_PREIMAGE_BITS);
+
+    let mut nullifier_preimage = preimage_bits.to_vec();
+    nullifier_preimage.extend(constant_bits(&NULLIFIER_DOMAIN));
+    sha256(
+        cs.namespace(|| "sha256(amount + nonce + blinding + domain)"),
+        &nullifier_preimage,
+    )
+}
+
+// Enforces that two equal-length bit strings differ in at least one bit
+fn enforce_distinct<E: Engine, CS: ConstraintSystem<E>>(
+    mut cs: CS,
+    a: &[Boolean],
+    b: &[Boolean],
+) -> Result<(), SynthesisError> {
+    assert_eq!(a.len(), b.len());
+
+    let mut all_equal = Boolean::constant(true);
+    for (i, (a_bit, b_bit)) in a.iter().zip(b.iter()).enumerate() {
+        let differs = Boolean::xor(cs.namespace(|| format!("bit {} differs", i)), a_bit, b_bit)?;
+        all_equal = Boolean::and(
+            cs.namespace(|| format!("equal up to bit {}", i)),
+            &all_equal,
+            &differs.not(),
+        )?;
     }
+
+    cs.enforce(
+        || "not all bits equal",
+        |lc| lc + &all_equal.lc(CS::one(), E::Fr::one()),
+        |lc| lc + CS::one(),
+        |lc| lc,
+    );
+
+    Ok(())
 }
 
 struct Mixer {
     inputs: Vec<Amount>,
     outputs: Vec<Amount>,
+    fee: u128,
+    min_output: u128,
+    nonce_range: NonceRange,
+    hash: HashFunction,
 }
 
 impl Mixer {
+    // Zero-valued notes with distinct nonces, enough to lay out the circuit
+    fn with_shape(inputs_size: u8, outputs_size: u8, hash: HashFunction) -> Self {
+        let notes = |size: u8, first_nonce: u8| {
+            (0..size)
+                .map(|i| Amount::new(Value(0), Nonce(first_nonce as u128 + i as u128)))
+                .collect::<Vec<_>>()
+        };
+
+        Mixer {
+            inputs: notes(inputs_size, 1),
+            outputs: notes(outputs_size, inputs_size.wrapping_add(1)),
+            fee: 0,
+            min_output: 0,
+            nonce_range: NonceRange::default(),
+            hash,
+        }
+    }
+
     pub fn recursive_hash<E: Engine, CS: ConstraintSystem<E>>(
         mut cs: CS,
-        amounts: Vec<Amount>,
+        hashes: Vec<Vec<Boolean>>,
+        hash: HashFunction,
     ) -> Result<Vec<Boolean>, SynthesisError> {
-        let hashes = amounts
-            .into_iter()
-            .map(|a| a.hash(&mut cs))
-            .collect::<Result<Vec<Vec<_>>, _>>()?;
-
         hashes
             .into_iter()
             .try_fold(Vec::new(), |acc: Vec<Boolean>, h| {
-                let mut combined_bits = Vec::with_capacity(256);
+                let mut combined_bits = constant_bits(&MIXER_FOLD_DOMAIN);
                 combined_bits.extend(acc);
                 combined_bits.extend(h);
 
-                sha256(cs.namespace(|| "recursive hash amount"), &combined_bits)
+                hash.hash_bits(cs.namespace(|| "recursive hash amount"), &combined_bits)
             })
     }
 }
 
+// Canonicalizes first, so proofs always match `Witness::to_input`
+impl From<Witness> for Mixer {
+    fn from(mut witness: Witness) -> Self {
+        witness.canonicalize();
+        Mixer {
+            inputs: std::mem::take(&mut witness.inputs),
+            outputs: std::mem::take(&mut witness.outputs),
+            fee: witness.fee,
+            min_output: witness.min_output,
+            nonce_range: witness.nonce_range,
+            hash: witness.hash,
+        }
+    }
+}
+
+// bellman drops the circuit once it is synthesized, and the proving functions
+// take the witness by value, so this is where its notes get cleared
+#[cfg(feature = "zeroize")]
+impl Drop for Mixer {
+    fn drop(&mut self) {
+        self.inputs.iter_mut().for_each(Zeroize::zeroize);
+        self.outputs.iter_mut().for_each(Zeroize::zeroize);
+        self.fee.zeroize();
+        self.min_output.zeroize();
+    }
+}
+
 impl<E: Engine> Circuit<E> for Mixer {
     fn synthesize<CS: ConstraintSystem<E>>(self, mut cs: &mut CS) -> Result<(), SynthesisError> {
-        let inputs_sum: u128 = self.inputs.iter().map(|a| a.value).sum();
-        let outputs_sum: u128 = self.outputs.iter().map(|a| a.value).sum();
-        if inputs_sum < outputs_sum {
+        let values = |amounts: &[Amount]| sum_values(amounts.iter().map(Amount::value_u256));
+        let low_limbs = |amounts: &[Amount]| sum_values(amounts.iter().map(|a| a.value.into()));
+        let inputs_sum = values(&self.inputs).ok_or(SynthesisError::Unsatisfiable)?;
+        let outputs_sum = values(&self.outputs).and_then(|sum| sum.checked_add(self.fee.into()));
+        if Some(inputs_sum) != outputs_sum {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        if self
+            .outputs
+            .iter()
+            .any(|a| a.value_u256() < self.min_output.into())
+        {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+        if !self
+            .outputs
+            .iter()
+            .all(|a| self.nonce_range.contains(a.nonce))
+        {
             return Err(SynthesisError::Unsatisfiable);
         }
 
-        let amounts = self
-            .inputs
-            .into_iter()
-            .chain(self.outputs.into_iter())
+        // The low limb sums differ by a multiple of 2^128 that the high limbs
+        // make up for, offset by 2^8 so it can be allocated as unsigned bits
+        let carry = match (
+            low_limbs(&self.inputs),
+            low_limbs(&self.outputs).and_then(|sum| sum.checked_add(self.fee.into())),
+        ) {
+            (Some(inputs), Some(outputs)) => (CARRY_OFFSET + inputs.hi)
+                .checked_sub(outputs.hi)
+                .filter(|carry| *carry < 1 << CARRY_BITS)
+                .ok_or(SynthesisError::Unsatisfiable)?,
+            _ => return Err(SynthesisError::Unsatisfiable),
+        };
+
+        let shape_bits = [self.inputs.len() as u8, self.outputs.len() as u8]
+            .iter()
+            .flat_map(|byte| {
+                (0..8)
+                    .rev()
+                    .map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8))
+            })
             .collect::<Vec<_>>();
 
-        let recursive_hash = Mixer::recursive_hash(&mut cs, amounts)?;
-        multipack::pack_into_inputs(cs.namespace(|| "recursive hash"), &recursive_hash)
+        let mut hashes = Vec::with_capacity(self.inputs.len() + self.outputs.len());
+        let mut nullifiers = Vec::with_capacity(self.inputs.len());
+        let mut inputs_hi_lc = LinearCombination::zero();
+        let mut inputs_lo_lc = LinearCombination::zero();
+        for input in self.inputs.iter() {
+            let preimage_bits = input.alloc_preimage(&mut cs)?;
+            inputs_hi_lc = inputs_hi_lc + &bits_to_lc::<E>(CS::one(), &preimage_bits[..128]);
+            inputs_lo_lc = inputs_lo_lc + &bits_to_lc::<E>(CS::one(), &preimage_bits[128..256]);
+            hashes.push(hash_preimage(&mut cs, &preimage_bits, self.hash)?);
+            nullifiers.push(nullify_preimage(&mut cs, &preimage_bits)?);
+        }
+
+        // The same note spent twice in one proof would count its value twice
+        for i in 0..self.inputs.len() {
+            for j in i + 1..self.inputs.len() {
+                enforce_distinct(
+                    cs.namespace(|| format!("input {} != input {}", i, j)),
+                    &hashes[i],
+                    &hashes[j],
+                )?;
+            }
+        }
+
+        let min_output_bits = alloc_bits(
+            cs.namespace(|| "min output bits"),
+            &u128_to_circuit_bits(self.min_output),
+        )?;
+        let min_output_lc = bits_to_lc::<E>(CS::one(), &min_output_bits);
+        let min_nonce_bits = alloc_bits(
+            cs.namespace(|| "min nonce bits"),
+            &u128_to_circuit_bits(self.nonce_range.min),
+        )?;
+        let max_nonce_bits = alloc_bits(
+            cs.namespace(|| "max nonce bits"),
+            &u128_to_circuit_bits(self.nonce_range.max),
+        )?;
+        let min_nonce_lc = bits_to_lc::<E>(CS::one(), &min_nonce_bits);
+        let max_nonce_lc = bits_to_lc::<E>(CS::one(), &max_nonce_bits);
+
+        let mut outputs_hi_lc = LinearCombination::zero();
+        let mut outputs_lo_lc = LinearCombination::zero();
+        for (i, output) in self.outputs.iter().enumerate() {
+            let preimage_bits = output.alloc_preimage(&mut cs)?;
+            let value_hi_lc = bits_to_lc::<E>(CS::one(), &preimage_bits[..128]);
+            let value_lo_lc = bits_to_lc::<E>(CS::one(), &preimage_bits[128..256]);
+            outputs_hi_lc = outputs_hi_lc + &value_hi_lc;
+            outputs_lo_lc = outputs_lo_lc + &value_lo_lc;
+            hashes.push(hash_preimage(&mut cs, &preimage_bits, self.hash)?);
+
+            // value = min output + excess, one limb at a time: the low limb
+            // borrows from the high one when it is below the minimum, and
+            // both excess limbs are range checked to 128 bits so nothing
+            // wraps around the field
+            let borrow = output.value < self.min_output;
+            let borrow_bit = Boolean::from(AllocatedBit::alloc(
+                cs.namespace(|| format!("output {} borrow", i)),
+                Some(borrow),
+            )?);
+            let borrow_lc = borrow_bit.lc(CS::one(), E::Fr::one());
+            let excess_lo_bits = alloc_bits(
+                cs.namespace(|| format!("output {} excess lo bits", i)),
+                &u128_to_circuit_bits(output.value.wrapping_sub(self.min_output)),
+            )?;
+            let excess_hi_bits = alloc_bits(
+                cs.namespace(|| format!("output {} excess hi bits", i)),
+                &u128_to_circuit_bits(output.value_hi.wrapping_sub(borrow as u128)),
+            )?;
+
+            cs.enforce(
+                || format!("output {} low limb >= min output", i),
+                |lc| lc + &value_lo_lc + (pow2::<E>(128), &borrow_lc),
+                |lc| lc + CS::one(),
+                |lc| lc + &min_output_lc + &bits_to_lc::<E>(CS::one(), &excess_lo_bits),
+            );
+            cs.enforce(
+                || format!("output {} high limb covers borrow", i),
+                |lc| lc + &value_hi_lc,
+                |lc| lc + CS::one(),
+                |lc| lc + &borrow_lc + &bits_to_lc::<E>(CS::one(), &excess_hi_bits),
+            );
+
+            // min nonce + above = nonce = max nonce - below, with both gaps
+            // range checked to 128 bits
+            let nonce_lc = bits_to_lc::<E>(CS::one(), &preimage_bits[256..384]);
+            let above_min_bits = alloc_bits(
+                cs.namespace(|| format!("output {} nonce above min bits", i)),
+                &u128_to_circuit_bits(output.nonce.wrapping_sub(self.nonce_range.min)),
+            )?;
+            let below_max_bits = alloc_bits(
+                cs.namespace(|| format!("output {} nonce below max bits", i)),
+                &u128_to_circuit_bits(self.nonce_range.max.wrapping_sub(output.nonce)),
+            )?;
+            cs.enforce(
+                || format!("output {} nonce >= min nonce", i),
+                |lc| lc + &nonce_lc,
+                |lc| lc + CS::one(),
+                |lc| lc + &min_nonce_lc + &bits_to_lc::<E>(CS::one(), &above_min_bits),
+            );
+            cs.enforce(
+                || format!("output {} nonce <= max nonce", i),
+                |lc| lc + &nonce_lc + &bits_to_lc::<E>(CS::one(), &below_max_bits),
+                |lc| lc + CS::one(),
+                |lc| lc + &max_nonce_lc,
+            );
+        }
+
+        let fee_bits = alloc_bits(cs.namespace(|| "fee bits"), &u128_to_circuit_bits(self.fee))?;
+        let fee_lc = bits_to_lc::<E>(CS::one(), &fee_bits);
+
+        let carry_bits = alloc_bits(
+            cs.namespace(|| "carry bits"),
+            &u128_to_circuit_bits(carry)[128 - CARRY_BITS..],
+        )?;
+        let carry_lc =
+            bits_to_lc::<E>(CS::one(), &carry_bits) - (pow2::<E>(CARRY_BITS - 1), CS::one());
+
+        // Limb sums stay far below the field modulus, so these hold over the
+        // integers and together give inputs = outputs + fee
+        cs.enforce(
+            || "low limbs: inputs = outputs + fee + carry",
+            |lc| lc + &inputs_lo_lc,
+            |lc| lc + CS::one(),
+            |lc| lc + &outputs_lo_lc + &fee_lc + (pow2::<E>(128), &carry_lc),
+        );
+        cs.enforce(
+            || "high limbs: inputs + carry = outputs",
+            |lc| lc + &inputs_hi_lc + &carry_lc,
+            |lc| lc + CS::one(),
+            |lc| lc + &outputs_hi_lc,
+        );
+
+        let recursive_hash = Mixer::recursive_hash(&mut cs, hashes, self.hash)?;
+
+        let mut shaped_preimage = shape_bits;
+        shaped_preimage.extend(recursive_hash);
+        let shaped_hash = sha256(cs.namespace(|| "sha256(shape + hash)"), &shaped_preimage)?;
+
+        multipack::pack_into_inputs(cs.namespace(|| "recursive hash"), &shaped_hash)?;
+
+        for (i, nullifier) in nullifiers.iter().enumerate() {
+            multipack::pack_into_inputs(cs.namespace(|| format!("nullifier {}", i)), nullifier)?;
+        }
+
+        multipack::pack_into_inputs(cs.namespace(|| "fee"), &fee_bits)?;
+
+        multipack::pack_into_inputs(cs.namespace(|| "min output"), &min_output_bits)?;
+
+        multipack::pack_into_inputs(cs.namespace(|| "min nonce"), &min_nonce_bits)?;
+
+        multipack::pack_into_inputs(cs.namespace(|| "max nonce"), &max_nonce_bits)
+    }
+}
+
+fn write_point<G: CurveAffine, W: Write>(
+    writer: &mut W,
+    point: &G,
+    compressed: bool,
+) -> std::io::Result<()> {
+    if compressed {
+        writer.write_all(point.into_compressed().as_ref())
+    } else {
+        writer.write_all(point.into_uncompressed().as_ref())
+    }
+}
+
+fn read_point<G: CurveAffine, R: Read>(reader: &mut R, compressed: bool) -> std::io::Result<G> {
+    let point = if compressed {
+        let mut repr = G::Compressed::empty();
+        reader.read_exact(repr.as_mut())?;
+        repr.into_affine()
+    } else {
+        let mut repr = G::Uncompressed::empty();
+        reader.read_exact(repr.as_mut())?;
+        repr.into_affine()
+    };
+
+    point.map_err(|err| std::io::Error::new(std::io::ErrorKind::InvalidData, err))
+}
+
+// Length prefixed like bellman's own encoding
+fn write_points<G: CurveAffine, W: Write>(
+    writer: &mut W,
+    points: &[G],
+    compressed: bool,
+) -> std::io::Result<()> {
+    writer.write_all(&(points.len() as u32).to_be_bytes())?;
+    for point in points {
+        write_point(writer, point, compressed)?;
     }
+    Ok(())
+}
+
+// Bellman rejects the point at infinity in a proving key, and so does this
+fn read_points<G: CurveAffine, R: Read>(
+    reader: &mut R,
+    compressed: bool,
+) -> std::io::Result<Vec<G>> {
+    let mut len = [0u8; 4];
+    reader.read_exact(&mut len)?;
+    (0..u32::from_be_bytes(len))
+        .map(|_| {
+            let point = read_point::<G, _>(reader, compressed)?;
+            if point.is_zero() {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "point at infinity",
+                ));
+            }
+            Ok(point)
+        })
+        .collect()
 }
 
-pub struct Params<E: Engine>(groth16::Parameters<E>);
+// The groth16 parameters and the (inputs, outputs) shape they were set up
+// for. Serialized as a two byte shape header followed by bellman's encoding.
+pub struct Params<E: Engine>(groth16::Parameters<E>, (u8, u8));
+
+fn read_shape<R: Read>(reader: &mut R) -> std::io::Result<(u8, u8)> {
+    let mut shape = [0u8; 2];
+    reader.read_exact(&mut shape)?;
+    Ok((shape[0], shape[1]))
+}
 
 impl<E: Engine> Params<E> {
     pub fn verifying_key(&self) -> Vec<u8> {
@@ -110,140 +787,2910 @@ impl<E: Engine> Params<E> {
         bytes
     }
 
+    // Numbers of inputs and outputs a witness must have to be proven
+    pub fn shape(&self) -> (u8, u8) {
+        self.1
+    }
+
     pub fn to_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        self.0.write(&mut bytes).expect("write params");
+        self.write(&mut bytes).expect("write params");
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Params<E>, std::io::Error> {
-        let p = groth16::Parameters::read(bytes, true)?;
-        Ok(Params(p))
+    fn write<W: Write>(&self, mut writer: W) -> std::io::Result<()> {
+        let (inputs_size, outputs_size) = self.1;
+        writer.write_all(&[inputs_size, outputs_size])?;
+        self.0.write(writer)
     }
-}
-
-pub fn trust_setup(inputs_size: u8, outputs_size: u8) -> Params<Bls12> {
-    let params = {
-        let c = Mixer {
-            inputs: (0..inputs_size)
-                .map(|_| Amount::new(254, 1))
-                .collect::<Vec<_>>(),
-            outputs: (0..outputs_size)
-                .map(|_| Amount::new(1, 1))
-                .collect::<Vec<_>>(),
-        };
 
-        groth16::generate_random_parameters::<Bls12, _, _>(c, &mut OsRng).expect("setup")
-    };
+    pub fn from_bytes(bytes: &[u8]) -> Result<Params<E>, std::io::Error> {
+        Self::from_reader(bytes)
+    }
 
-    Params(params)
-}
+    pub fn from_reader<R: Read>(mut reader: R) -> std::io::Result<Params<E>> {
+        let shape = read_shape(&mut reader)?;
+        let p = groth16::Parameters::read(reader, true)?;
+        Ok(Params(p, shape))
+    }
 
-#[derive(Debug, Clone)]
-pub struct Witness {
-    pub inputs: Vec<Amount>,
-    pub outputs: Vec<Amount>,
-}
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        self.write(BufWriter::new(File::create(path)?))
+    }
 
-pub struct Proof(groth16::Proof<Bls12>);
+    pub fn load(path: &Path) -> std::io::Result<Params<E>> {
+        Self::from_reader(BufReader::new(File::open(path)?))
+    }
 
-impl Proof {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    // Same layout as `to_bytes` with every point compressed, about half the
+    // size but slower to read since each point has to be decompressed.
+    // `from_bytes` doesn't detect this format, read it with
+    // `from_bytes_compressed`.
+    pub fn to_bytes_compressed(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        self.0.write(&mut bytes).expect("write params");
+        self.write_compressed(&mut bytes).expect("write params");
         bytes
     }
 
-    pub fn from_bytes(bytes: &[u8]) -> Result<Proof, std::io::Error> {
-        let p = groth16::Proof::read(bytes)?;
-        Ok(Proof(p))
-    }
-}
+    fn write_compressed<W: Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        let (inputs_size, outputs_size) = self.1;
+        writer.write_all(&[inputs_size, outputs_size])?;
 
-pub fn generate_proof(witness: Witness, params: &Vec<u8>) -> Proof {
-    let c = Mixer {
-        inputs: witness.inputs,
-        outputs: witness.outputs,
-    };
+        let vk = &self.0.vk;
+        write_point(writer, &vk.alpha_g1, true)?;
+        write_point(writer, &vk.beta_g1, true)?;
+        write_point(writer, &vk.beta_g2, true)?;
+        write_point(writer, &vk.gamma_g2, true)?;
+        write_point(writer, &vk.delta_g1, true)?;
+        write_point(writer, &vk.delta_g2, true)?;
+        write_points(writer, &vk.ic, true)?;
+
+        write_points(writer, &self.0.h, true)?;
+        write_points(writer, &self.0.l, true)?;
+        write_points(writer, &self.0.a, true)?;
+        write_points(writer, &self.0.b_g1, true)?;
+        write_points(writer, &self.0.b_g2, true)
+    }
 
-    let params = Params::from_bytes(params.as_ref()).expect("read params");
-    let proof = groth16::create_random_proof(c, &params.0, &mut OsRng).expect("create proof");
+    pub fn from_bytes_compressed(mut bytes: &[u8]) -> Result<Params<E>, std::io::Error> {
+        let reader = &mut bytes;
+        let shape = read_shape(reader)?;
+        let vk = groth16::VerifyingKey {
+            alpha_g1: read_point(reader, true)?,
+            beta_g1: read_point(reader, true)?,
+            beta_g2: read_point(reader, true)?,
+            gamma_g2: read_point(reader, true)?,
+            delta_g1: read_point(reader, true)?,
+            delta_g2: read_point(reader, true)?,
+            ic: read_points(reader, true)?,
+        };
 
-    Proof(proof)
+        Ok(Params(
+            groth16::Parameters {
+                vk,
+                h: Arc::new(read_points(reader, true)?),
+                l: Arc::new(read_points(reader, true)?),
+                a: Arc::new(read_points(reader, true)?),
+                b_g1: Arc::new(read_points(reader, true)?),
+                b_g2: Arc::new(read_points(reader, true)?),
+            },
+            shape,
+        ))
+    }
 }
 
-pub struct Input {
-    recursive_hash: Vec<u8>,
+// Params are serialized with the verifying key right after the shape header,
+// so the proving key never needs to be parsed
+pub fn verifying_key_from_params_bytes(mut bytes: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    read_shape(&mut bytes)?;
+    let vk = groth16::VerifyingKey::<Bls12>::read(bytes)?;
+
+    let mut vk_bytes = Vec::new();
+    vk.write(&mut vk_bytes)?;
+    Ok(vk_bytes)
 }
 
-struct VerifyingKey(groth16::VerifyingKey<Bls12>);
+pub fn trust_setup(inputs_size: u8, outputs_size: u8) -> Params<Bls12> {
+    trust_setup_for::<Bls12>(inputs_size, outputs_size)
+}
 
-impl VerifyingKey {
-    fn from_bytes(bytes: &[u8]) -> Result<VerifyingKey, std::io::Error> {
-        let k = groth16::VerifyingKey::read(bytes)?;
-        Ok(VerifyingKey(k))
-    }
+// `trust_setup` on any pairing engine, for verifiers that don't use BLS12-381
+pub fn trust_setup_for<E: Engine>(inputs_size: u8, outputs_size: u8) -> Params<E> {
+    setup(inputs_size, outputs_size, HashFunction::Sha256)
 }
 
-pub fn verify(vk_bytes: &Vec<u8>, proof: &Vec<u8>, input: Input) -> bool {
-    let verifying_key = VerifyingKey::from_bytes(vk_bytes).expect("read verifying key");
-    let verifying_key = groth16::prepare_verifying_key(&verifying_key.0);
+// Params for witnesses hashing with `hash`. The params don't record it, so a
+// witness proved with another hash makes a proof that fails to verify.
+pub fn trust_setup_with_hash(
+    inputs_size: u8,
+    outputs_size: u8,
+    hash: HashFunction,
+) -> Params<Bls12> {
+    setup(inputs_size, outputs_size, hash)
+}
 
-    let hash_bits = multipack::bytes_to_bits(&input.recursive_hash);
-    let inputs = multipack::compute_multipacking::<Bls12>(&hash_bits);
+fn setup<E: Engine>(inputs_size: u8, outputs_size: u8, hash: HashFunction) -> Params<E> {
+    let params = {
+        let c = Mixer::with_shape(inputs_size, outputs_size, hash);
+        groth16::generate_random_parameters::<E, _, _>(c, &mut OsRng).expect("setup")
+    };
 
-    let proof = Proof::from_bytes(proof.as_ref()).expect("read proof");
+    Params(params, (inputs_size, outputs_size))
+}
 
-    groth16::verify_proof::<Bls12>(&verifying_key, &proof.0, &inputs).expect("verify proof")
+// Params per (inputs, outputs) shape, set up the first time a shape is asked
+// for. The lock is held during setup so a shape is never set up twice.
+#[derive(Default)]
+pub struct ParamsCache {
+    params: Mutex<HashMap<(u8, u8), SharedParams>>,
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+type SharedParams = Arc<Params<Bls12>>;
 
-    use sha2::{Digest, Sha256};
+impl ParamsCache {
+    pub fn new() -> Self {
+        ParamsCache::default()
+    }
 
-    fn combine(amount: u128, nonce: u128) -> [u8; 32] {
-        let amount_bytes = amount.to_be_bytes();
-        let nonce_bytes = nonce.to_be_bytes();
+    pub fn get_or_setup(&self, inputs_size: u8, outputs_size: u8) -> Arc<Params<Bls12>> {
+        let mut params = self.params.lock().expect("params cache poisoned");
+        params
+            .entry((inputs_size, outputs_size))
+            .or_insert_with(|| Arc::new(trust_setup(inputs_size, outputs_size)))
+            .clone()
+    }
 
-        let mut bytes = [0u8; 32];
-        for i in 0..16 {
-            bytes[i] = amount_bytes[i];
-            bytes[i + 16] = nonce_bytes[i];
-        }
+    // Number of shapes set up so far
+    pub fn len(&self) -> usize {
+        self.params.lock().expect("params cache poisoned").len()
+    }
 
-        bytes
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
+}
 
-    #[test]
-    fn basic_test() {
-        let params = trust_setup(2, 1); // Support up to 2 inputs and 1 outputs
-        println!("complete trust setup");
+// Only counts constraints. bellman's TestConstraintSystem would also evaluate
+// them, and it rejects the repeated namespace paths the hash loops create.
+#[derive(Default)]
+struct ConstraintCounter {
+    inputs: usize,
+    aux: usize,
+    constraints: usize,
+}
 
-        let witness = Witness {
-            inputs: vec![Amount::new(1, 1), Amount::new(2, 2)],
-            outputs: vec![Amount::new(3, 2)],
-        };
+impl<E: Engine> ConstraintSystem<E> for ConstraintCounter {
+    type Root = Self;
 
-        let proof = generate_proof(witness, &params.to_bytes());
-        println!("complete generate proof");
+    fn alloc<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.aux - 1)))
+    }
 
-        let amounts = vec![combine(1, 1), combine(2, 2), combine(3, 2)];
+    fn alloc_input<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        // Input 0 is the constant one
+        self.inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(self.inputs)))
+    }
 
-        let amount_hashes = amounts
-            .into_iter()
-            .map(|a| Sha256::digest(&a).to_vec())
-            .collect::<Vec<_>>();
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, _: LA, _: LB, _: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        self.constraints += 1;
+    }
 
-        let recursive_hash = amount_hashes.into_iter().fold(Vec::new(), |mut acc, h| {
-            acc.extend(h);
-            Sha256::digest(&acc).to_vec()
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+// Constraints `trust_setup` would lay out for this shape, without running it
+pub fn constraint_count(inputs_size: u8, outputs_size: u8) -> usize {
+    constraint_count_with_hash(inputs_size, outputs_size, HashFunction::Sha256)
+}
+
+pub fn constraint_count_with_hash(inputs_size: u8, outputs_size: u8, hash: HashFunction) -> usize {
+    let mut counter = ConstraintCounter::default();
+    Circuit::<Bls12>::synthesize(
+        Mixer::with_shape(inputs_size, outputs_size, hash),
+        &mut counter,
+    )
+    .expect("synthesize");
+    counter.constraints
+}
+
+// Records every constraint for `export_r1cs`
+#[cfg(feature = "json")]
+#[derive(Default)]
+struct R1csRecorder {
+    inputs: usize,
+    aux: usize,
+    constraints: Vec<[Vec<(Index, Fr)>; 3]>,
+}
+
+#[cfg(feature = "json")]
+impl ConstraintSystem<Bls12> for R1csRecorder {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux += 1;
+        Ok(Variable::new_unchecked(Index::Aux(self.aux - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, _: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        // Input 0 is the constant one
+        self.inputs += 1;
+        Ok(Variable::new_unchecked(Index::Input(self.inputs)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<Bls12>) -> LinearCombination<Bls12>,
+        LB: FnOnce(LinearCombination<Bls12>) -> LinearCombination<Bls12>,
+        LC: FnOnce(LinearCombination<Bls12>) -> LinearCombination<Bls12>,
+    {
+        let terms = |lc: LinearCombination<Bls12>| {
+            lc.as_ref()
+                .iter()
+                .map(|(variable, coeff)| (variable.get_unchecked(), *coeff))
+                .collect()
+        };
+        self.constraints.push([
+            terms(a(LinearCombination::zero())),
+            terms(b(LinearCombination::zero())),
+            terms(c(LinearCombination::zero())),
+        ]);
+    }
+
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+// Variables are numbered with the constant one first, then the public
+// inputs, then the aux variables. Each term is [variable, coefficient], the
+// coefficient as big-endian hex.
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct R1csJson {
+    num_inputs: usize,
+    num_aux: usize,
+    constraints: Vec<R1csConstraintJson>,
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct R1csConstraintJson {
+    a: Vec<(usize, String)>,
+    b: Vec<(usize, String)>,
+    c: Vec<(usize, String)>,
+}
+
+// Writes the constraints `trust_setup` lays out for this shape as JSON, each
+// one an A, B, C triple of linear combinations with A * B = C, for auditing
+// or another proving backend
+#[cfg(feature = "json")]
+pub fn export_r1cs(inputs_size: u8, outputs_size: u8, path: &Path) -> std::io::Result<()> {
+    let mut recorder = R1csRecorder::default();
+    Circuit::<Bls12>::synthesize(
+        Mixer::with_shape(inputs_size, outputs_size, HashFunction::Sha256),
+        &mut recorder,
+    )
+    .map_err(std::io::Error::other)?;
+
+    let num_inputs = recorder.inputs + 1;
+    let terms = |lc: &[(Index, Fr)]| {
+        lc.iter()
+            .map(|(index, coeff)| {
+                let variable = match index {
+                    Index::Input(i) => *i,
+                    Index::Aux(i) => num_inputs + i,
+                };
+                let mut bytes = vec![];
+                coeff.into_repr().write_be(&mut bytes).expect("write coeff");
+                (variable, hex::encode(bytes))
+            })
+            .collect()
+    };
+    let json = R1csJson {
+        num_inputs,
+        num_aux: recorder.aux,
+        constraints: recorder
+            .constraints
+            .iter()
+            .map(|[a, b, c]| R1csConstraintJson {
+                a: terms(a),
+                b: terms(b),
+                c: terms(c),
+            })
+            .collect(),
+    };
+
+    let writer = BufWriter::new(File::create(path)?);
+    serde_json::to_writer(writer, &json)?;
+    Ok(())
+}
+
+// Evaluates every constraint against the witness values. Used instead of
+// bellman's TestConstraintSystem for the same reason as `ConstraintCounter`.
+struct WitnessChecker<E: Engine> {
+    inputs: Vec<E::Fr>,
+    aux: Vec<E::Fr>,
+    satisfied: bool,
+}
+
+impl<E: Engine> WitnessChecker<E> {
+    fn new() -> Self {
+        WitnessChecker {
+            inputs: vec![E::Fr::one()],
+            aux: vec![],
+            satisfied: true,
+        }
+    }
+
+    fn eval(&self, lc: &LinearCombination<E>) -> E::Fr {
+        lc.as_ref()
+            .iter()
+            .fold(E::Fr::zero(), |mut acc, (variable, coeff)| {
+                let mut term = match variable.get_unchecked() {
+                    Index::Input(i) => self.inputs[i],
+                    Index::Aux(i) => self.aux[i],
+                };
+                term.mul_assign(coeff);
+                acc.add_assign(&term);
+                acc
+            })
+    }
+}
+
+impl<E: Engine> ConstraintSystem<E> for WitnessChecker<E> {
+    type Root = Self;
+
+    fn alloc<F, A, AR>(&mut self, _: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.aux.push(f()?);
+        Ok(Variable::new_unchecked(Index::Aux(self.aux.len() - 1)))
+    }
+
+    fn alloc_input<F, A, AR>(&mut self, _: A, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<E::Fr, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.inputs.push(f()?);
+        Ok(Variable::new_unchecked(Index::Input(self.inputs.len() - 1)))
+    }
+
+    fn enforce<A, AR, LA, LB, LC>(&mut self, _: A, a: LA, b: LB, c: LC)
+    where
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+        LA: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LB: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+        LC: FnOnce(LinearCombination<E>) -> LinearCombination<E>,
+    {
+        let mut ab = self.eval(&a(LinearCombination::zero()));
+        ab.mul_assign(&self.eval(&b(LinearCombination::zero())));
+        if ab != self.eval(&c(LinearCombination::zero())) {
+            self.satisfied = false;
+        }
+    }
+
+    fn push_namespace<NR, N>(&mut self, _: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+    }
+
+    fn pop_namespace(&mut self) {}
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
+
+// Synthesizes the circuit for `witness` without proving, to catch a bad
+// witness before spending time on `generate_proof`
+pub fn check_witness(witness: &Witness) -> Result<(), SynthesisError> {
+    let c = Mixer::from(witness.clone());
+
+    let mut checker = WitnessChecker::<Bls12>::new();
+    c.synthesize(&mut checker)?;
+    if !checker.satisfied {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct Witness {
+    pub inputs: Vec<Amount>,
+    pub outputs: Vec<Amount>,
+    // Taken by the relayer: inputs must sum to outputs plus the fee
+    #[cfg_attr(feature = "serde", serde(with = "u128_string"))]
+    pub fee: u128,
+    // Public lower bound on every output value
+    #[cfg_attr(feature = "serde", serde(default, with = "u128_string"))]
+    pub min_output: u128,
+    // Public bounds on every output nonce
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub nonce_range: NonceRange,
+    // Must match the hash the params were set up with
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub hash: HashFunction,
+}
+
+impl Witness {
+    // The recursive hash depends on note order, so inputs and outputs are
+    // sorted by nonce, then value, then blinding, which covers every field a
+    // note hash commits to. Proving and `to_input` both do this.
+    pub fn canonicalize(&mut self) {
+        let key = |a: &Amount| (a.nonce, a.value_u256(), a.blinding);
+        self.inputs.sort_by_key(key);
+        self.outputs.sort_by_key(key);
+    }
+
+    // Sorts a copy, which clears itself on drop with the zeroize feature
+    pub fn to_input(&self) -> Input {
+        let mut witness = self.clone();
+        witness.canonicalize();
+
+        compute_public_input_with_nonce_range(
+            &witness.inputs,
+            &witness.outputs,
+            witness.fee,
+            witness.min_output,
+            witness.nonce_range,
+            witness.hash,
+        )
+    }
+}
+
+// `Amount` is `Copy`, so it can't clear itself on drop. A `Witness` clears its
+// notes when dropped; call `zeroize` on any note copied out of one.
+#[cfg(feature = "zeroize")]
+impl Zeroize for Amount {
+    fn zeroize(&mut self) {
+        self.value_hi.zeroize();
+        self.value.zeroize();
+        self.nonce.zeroize();
+        self.blinding.zeroize();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Zeroize for Witness {
+    fn zeroize(&mut self) {
+        self.inputs.iter_mut().for_each(Zeroize::zeroize);
+        self.outputs.iter_mut().for_each(Zeroize::zeroize);
+        self.fee.zeroize();
+        self.min_output.zeroize();
+        self.nonce_range = NonceRange::default();
+        self.hash = HashFunction::default();
+    }
+}
+
+#[cfg(feature = "zeroize")]
+impl Drop for Witness {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
+}
+
+// Compressed A, B and C
+pub const PROOF_SIZE_BYTES: usize = 48 + 96 + 48;
+
+// Uncompressed alpha, beta, gamma and delta points, then the length-prefixed
+// IC points, one per public input plus the constant term. Outputs only enter
+// the public input through the recursive hash, so their count doesn't matter.
+pub fn verifying_key_size(inputs_size: u8, _outputs_size: u8) -> usize {
+    let packed = |bits: usize| {
+        let capacity = Fr::CAPACITY as usize;
+        bits.div_ceil(capacity)
+    };
+    let public_inputs = packed(256) + inputs_size as usize * packed(256) + 4 * packed(128);
+
+    verifying_key_header_bytes::<Bls12>() + (public_inputs + 1) * g1_bytes::<Bls12>()
+}
+
+// Uncompressed sizes of a point in each group, as the verifying key holds them
+fn g1_bytes<E: Engine>() -> usize {
+    <E::G1Affine as CurveAffine>::Uncompressed::size()
+}
+
+fn g2_bytes<E: Engine>() -> usize {
+    <E::G2Affine as CurveAffine>::Uncompressed::size()
+}
+
+// Everything before the IC points: alpha, beta and delta in G1, beta, gamma
+// and delta in G2, then the four byte IC count
+fn verifying_key_header_bytes<E: Engine>() -> usize {
+    3 * g1_bytes::<E>() + 3 * g2_bytes::<E>() + 4
+}
+
+pub struct Proof<E: Engine = Bls12>(groth16::Proof<E>);
+
+impl<E: Engine> Proof<E> {
+    // Compressed points, the only format `from_bytes` reads
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        self.0.write(&mut bytes).expect("write params");
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Proof<E>, std::io::Error> {
+        Self::from_reader(bytes)
+    }
+
+    // bellman rejects A or C at infinity. Every point is decoded with
+    // pairing's `into_affine`, which rejects points off the curve or outside
+    // the prime order subgroup, so no small subgroup point gets through.
+    pub fn from_reader<R: Read>(reader: R) -> std::io::Result<Proof<E>> {
+        let p = groth16::Proof::read(reader)?;
+        Ok(Proof(p))
+    }
+
+    // Compressed A, B and C, for comparing with another verifier's encoding.
+    // Concatenated they are `to_bytes`.
+    pub fn components(&self) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+        (
+            self.0.a.into_compressed().as_ref().to_vec(),
+            self.0.b.into_compressed().as_ref().to_vec(),
+            self.0.c.into_compressed().as_ref().to_vec(),
+        )
+    }
+
+    // Twice the size of `to_bytes`, but reading it skips the square roots
+    // needed to decompress each point
+    pub fn to_bytes_uncompressed(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_point(&mut bytes, &self.0.a, false)
+            .and_then(|_| write_point(&mut bytes, &self.0.b, false))
+            .and_then(|_| write_point(&mut bytes, &self.0.c, false))
+            .expect("write proof");
+        bytes
+    }
+
+    // A fresh proof of the same statement that can't be linked to this one:
+    // A / r1, B * r1 + r1 * r2 * delta and C + r2 * A. Needs delta from the
+    // verifying key the proof was made for.
+    pub fn rerandomize<R: RngCore>(
+        &self,
+        vk_bytes: &[u8],
+        rng: &mut R,
+    ) -> Result<Proof<E>, std::io::Error> {
+        let vk = VerifyingKey::<E>::from_bytes(vk_bytes)?.0;
+
+        let r1 = loop {
+            let r = E::Fr::random(rng);
+            if !r.is_zero() {
+                break r;
+            }
+        };
+        let r2 = E::Fr::random(rng);
+        let mut r1r2 = r1;
+        r1r2.mul_assign(&r2);
+
+        let a = self.0.a.mul(r1.inverse().unwrap().into_repr());
+        let mut b = self.0.b.mul(r1.into_repr());
+        b.add_assign(&vk.delta_g2.mul(r1r2.into_repr()));
+        let mut c = self.0.a.mul(r2.into_repr());
+        c.add_assign_mixed(&self.0.c);
+
+        Ok(Proof(groth16::Proof {
+            a: a.into_affine(),
+            b: b.into_affine(),
+            c: c.into_affine(),
+        }))
+    }
+
+    pub fn from_bytes_uncompressed(mut bytes: &[u8]) -> Result<Proof<E>, std::io::Error> {
+        let reader = &mut bytes;
+        Ok(Proof(groth16::Proof {
+            a: read_point(reader, false)?,
+            b: read_point(reader, false)?,
+            c: read_point(reader, false)?,
+        }))
+    }
+}
+
+// Tags JSON encoded proofs and keys so a client can reject ones made for
+// another proof system or curve
+#[cfg(feature = "json")]
+const SCHEME: &str = "groth16-bls12-381";
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct ProofJson {
+    scheme: String,
+    proof: String,
+}
+
+#[cfg(feature = "json")]
+#[derive(Serialize, Deserialize)]
+struct VerifyingKeyJson {
+    scheme: String,
+    verifying_key: String,
+}
+
+#[cfg(feature = "json")]
+#[derive(Debug)]
+pub enum JsonError {
+    Json(serde_json::Error),
+    Hex(hex::FromHexError),
+    Scheme(String),
+    Bytes(std::io::Error),
+}
+
+#[cfg(feature = "json")]
+fn check_scheme(scheme: String) -> Result<(), JsonError> {
+    if scheme != SCHEME {
+        return Err(JsonError::Scheme(scheme));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+impl Proof<Bls12> {
+    pub fn to_json(&self) -> String {
+        let json = ProofJson {
+            scheme: SCHEME.to_string(),
+            proof: hex::encode(self.to_bytes()),
+        };
+        serde_json::to_string(&json).expect("serialize proof")
+    }
+
+    pub fn from_json(json: &str) -> Result<Proof<Bls12>, JsonError> {
+        let json: ProofJson = serde_json::from_str(json).map_err(JsonError::Json)?;
+        check_scheme(json.scheme)?;
+
+        let bytes = hex::decode(json.proof).map_err(JsonError::Hex)?;
+        Proof::from_bytes(&bytes).map_err(JsonError::Bytes)
+    }
+}
+
+#[cfg(feature = "json")]
+pub fn verifying_key_to_json(vk_bytes: &[u8]) -> String {
+    let json = VerifyingKeyJson {
+        scheme: SCHEME.to_string(),
+        verifying_key: hex::encode(vk_bytes),
+    };
+    serde_json::to_string(&json).expect("serialize verifying key")
+}
+
+// Returns the key bytes once they're known to parse
+#[cfg(feature = "json")]
+pub fn verifying_key_from_json(json: &str) -> Result<Vec<u8>, JsonError> {
+    let json: VerifyingKeyJson = serde_json::from_str(json).map_err(JsonError::Json)?;
+    check_scheme(json.scheme)?;
+
+    let bytes = hex::decode(json.verifying_key).map_err(JsonError::Hex)?;
+    VerifyingKey::<Bls12>::from_bytes(&bytes).map_err(JsonError::Bytes)?;
+    Ok(bytes)
+}
+
+#[derive(Debug)]
+pub enum ProveError {
+    Params(std::io::Error),
+    // Inputs and outputs the params were set up for, and the witness counts
+    Shape {
+        params: (u8, u8),
+        witness: (usize, usize),
+    },
+    Synthesis(SynthesisError),
+}
+
+pub fn generate_proof(witness: Witness, params: &[u8]) -> Result<Proof, ProveError> {
+    generate_proof_for::<Bls12>(witness, params)
+}
+
+pub fn generate_proof_for<E: Engine>(
+    witness: Witness,
+    params: &[u8],
+) -> Result<Proof<E>, ProveError> {
+    prove(witness, params, &mut OsRng)
+}
+
+// Same seed, witness and params always give the same proof bytes. Only for
+// tests, a proof whose randomness is known isn't zero knowledge.
+pub fn generate_proof_seeded(
+    witness: Witness,
+    params: &[u8],
+    seed: [u8; 32],
+) -> Result<Proof, ProveError> {
+    prove(witness, params, &mut ChaCha20Rng::from_seed(seed))
+}
+
+// Parses the params once and proves each witness on the rayon thread pool,
+// every proof drawing its randomness from the OS. Results are in witness
+// order. Params that fail to parse fail every witness.
+#[cfg(feature = "rayon")]
+pub fn generate_proofs_parallel(
+    witnesses: Vec<Witness>,
+    params: &[u8],
+) -> Vec<Result<Proof, ProveError>> {
+    use rayon::prelude::*;
+
+    let circuits = witnesses.into_iter().map(Mixer::from).collect::<Vec<_>>();
+    match Params::<Bls12>::from_bytes(params) {
+        Ok(params) => circuits
+            .into_par_iter()
+            .map(|circuit| prove_with_params(circuit, &params, &mut OsRng))
+            .collect(),
+        Err(err) => circuits
+            .iter()
+            .map(|_| {
+                Err(ProveError::Params(std::io::Error::new(
+                    err.kind(),
+                    err.to_string(),
+                )))
+            })
+            .collect(),
+    }
+}
+
+fn prove<E: Engine, R: RngCore>(
+    witness: Witness,
+    params: &[u8],
+    rng: &mut R,
+) -> Result<Proof<E>, ProveError> {
+    // Moved into the circuit first, so the notes are cleared on every path
+    let circuit = Mixer::from(witness);
+    let params = Params::<E>::from_bytes(params).map_err(ProveError::Params)?;
+
+    prove_with_params(circuit, &params, rng)
+}
+
+fn prove_with_params<E: Engine, R: RngCore>(
+    circuit: Mixer,
+    params: &Params<E>,
+    rng: &mut R,
+) -> Result<Proof<E>, ProveError> {
+    let (inputs_size, outputs_size) = params.shape();
+    if circuit.inputs.len() != inputs_size as usize
+        || circuit.outputs.len() != outputs_size as usize
+    {
+        return Err(ProveError::Shape {
+            params: params.shape(),
+            witness: (circuit.inputs.len(), circuit.outputs.len()),
+        });
+    }
+
+    let proof =
+        groth16::create_random_proof(circuit, &params.0, rng).map_err(ProveError::Synthesis)?;
+
+    Ok(Proof(proof))
+}
+
+#[derive(Debug)]
+pub enum ChainError {
+    Empty,
+    Disconnected(usize),
+    NotConserved { inputs: U256, outputs: U256 },
+    Overflow,
+    Prove(ProveError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AggregateReport {
+    pub steps: usize,
+    pub total_inputs: U256,
+    pub total_outputs: U256,
+    pub total_fees: u128,
+}
+
+// Outputs of each step must be spent as the inputs of the next one, and the
+// last step must release the value the first step took in, less the fees.
+pub fn prove_mixer_chain(
+    steps: &[Witness],
+    params: &[u8],
+) -> Result<(Vec<Proof>, AggregateReport), ChainError> {
+    let (first, last) = match (steps.first(), steps.last()) {
+        (Some(first), Some(last)) => (first, last),
+        _ => return Err(ChainError::Empty),
+    };
+
+    for (i, pair) in steps.windows(2).enumerate() {
+        if pair[0].outputs != pair[1].inputs {
+            return Err(ChainError::Disconnected(i));
+        }
+    }
+
+    let values = |amounts: &[Amount]| sum_values(amounts.iter().map(Amount::value_u256));
+    let total_inputs = values(&first.inputs).ok_or(ChainError::Overflow)?;
+    let total_outputs = values(&last.outputs).ok_or(ChainError::Overflow)?;
+    let total_fees = steps
+        .iter()
+        .try_fold(0u128, |acc, w| acc.checked_add(w.fee))
+        .ok_or(ChainError::Overflow)?;
+    if Some(total_inputs) != total_outputs.checked_add(total_fees.into()) {
+        return Err(ChainError::NotConserved {
+            inputs: total_inputs,
+            outputs: total_outputs,
+        });
+    }
+
+    let proofs = steps
+        .iter()
+        .map(|witness| generate_proof(witness.clone(), params))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(ChainError::Prove)?;
+
+    let report = AggregateReport {
+        steps: steps.len(),
+        total_inputs,
+        total_outputs,
+        total_fees,
+    };
+
+    Ok((proofs, report))
+}
+
+#[derive(Debug, PartialEq)]
+pub enum InputHexError {
+    Hex(hex::FromHexError),
+    // The trailing byte names no `HashFunction`
+    UnknownHash(u8),
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    VerifyingKey(std::io::Error),
+    Proof(std::io::Error),
+    Verification(SynthesisError),
+}
+
+// The only core failure is a public input count the key doesn't expect, which
+// bellman reports the same way
+impl From<verify_core::VerifyCoreError> for VerifyError {
+    fn from(err: verify_core::VerifyCoreError) -> Self {
+        match err {
+            verify_core::VerifyCoreError::MalformedVerifyingKey => {
+                VerifyError::Verification(SynthesisError::MalformedVerifyingKey)
+            }
+        }
+    }
+}
+
+// Single error type for callers that handle proving and verification failures
+// the same way. `ProveError` and `VerifyError` convert into it with `?`.
+#[derive(Debug)]
+pub enum Zk42Error {
+    Io(std::io::Error),
+    Synthesis(SynthesisError),
+    Shape {
+        params: (u8, u8),
+        witness: (usize, usize),
+    },
+}
+
+impl std::fmt::Display for Zk42Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Zk42Error::Io(err) => write!(f, "malformed bytes: {}", err),
+            Zk42Error::Synthesis(err) => write!(f, "synthesis failed: {}", err),
+            Zk42Error::Shape { params, witness } => write!(
+                f,
+                "params for {} inputs and {} outputs, witness has {} and {}",
+                params.0, params.1, witness.0, witness.1
+            ),
+        }
+    }
+}
+
+impl From<std::io::Error> for Zk42Error {
+    fn from(err: std::io::Error) -> Self {
+        Zk42Error::Io(err)
+    }
+}
+
+impl From<SynthesisError> for Zk42Error {
+    fn from(err: SynthesisError) -> Self {
+        Zk42Error::Synthesis(err)
+    }
+}
+
+impl From<VerifyError> for Zk42Error {
+    fn from(err: VerifyError) -> Self {
+        match err {
+            VerifyError::VerifyingKey(err) | VerifyError::Proof(err) => Zk42Error::Io(err),
+            VerifyError::Verification(err) => Zk42Error::Synthesis(err),
+        }
+    }
+}
+
+impl std::error::Error for Zk42Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Zk42Error::Io(err) => Some(err),
+            Zk42Error::Synthesis(err) => Some(err),
+            Zk42Error::Shape { .. } => None,
+        }
+    }
+}
+
+impl From<ProveError> for Zk42Error {
+    fn from(err: ProveError) -> Self {
+        match err {
+            ProveError::Params(err) => Zk42Error::Io(err),
+            ProveError::Shape { params, witness } => Zk42Error::Shape { params, witness },
+            ProveError::Synthesis(err) => Zk42Error::Synthesis(err),
+        }
+    }
+}
+
+pub struct Input {
+    recursive_hash: Vec<u8>,
+    num_inputs: u8,
+    num_outputs: u8,
+    nullifiers: Vec<Vec<u8>>,
+    fee: u128,
+    min_output: u128,
+    nonce_range: NonceRange,
+    hash: HashFunction,
+}
+
+impl Input {
+    // Commits the recursive hash to the number of inputs and outputs it folded
+    fn shaped_hash(&self) -> Vec<u8> {
+        let mut preimage = vec![self.num_inputs, self.num_outputs];
+        preimage.extend(&self.recursive_hash);
+
+        Sha256::digest(&preimage).to_vec()
+    }
+
+    // One nullifier per input note, in canonical order. Callers should reject
+    // a proof whose nullifiers have been seen before.
+    pub fn nullifiers(&self) -> &[Vec<u8>] {
+        &self.nullifiers
+    }
+
+    pub fn fee(&self) -> u128 {
+        self.fee
+    }
+
+    pub fn min_output(&self) -> u128 {
+        self.min_output
+    }
+
+    pub fn nonce_range(&self) -> NonceRange {
+        self.nonce_range
+    }
+
+    // Input and output counts, the recursive hash, each nullifier, the
+    // big-endian fee, minimum output and nonce bounds, then the hash function
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    // Every public field: the shape first, so the nullifier count is known
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.num_inputs, self.num_outputs];
+        bytes.extend(&self.recursive_hash);
+        for nullifier in self.nullifiers.iter() {
+            bytes.extend(nullifier);
+        }
+        bytes.extend(&self.fee.to_be_bytes());
+        bytes.extend(&self.min_output.to_be_bytes());
+        bytes.extend(&self.nonce_range.min.to_be_bytes());
+        bytes.extend(&self.nonce_range.max.to_be_bytes());
+        bytes.push(self.hash.to_byte());
+        bytes
+    }
+
+    pub fn from_hex(input_hex: &str) -> Result<Input, InputHexError> {
+        let bytes = hex::decode(input_hex).map_err(InputHexError::Hex)?;
+        if bytes.len() < 2 || bytes.len() != 2 + 32 + bytes[0] as usize * 32 + 4 * 16 + 1 {
+            return Err(InputHexError::Hex(hex::FromHexError::InvalidStringLength));
+        }
+        let tag = bytes[bytes.len() - 1];
+        let hash = HashFunction::from_byte(tag).ok_or(InputHexError::UnknownHash(tag))?;
+
+        let (hashes, amounts) = bytes[2..bytes.len() - 1].split_at(bytes.len() - 2 - 1 - 4 * 16);
+        let amount = |i: usize| {
+            let mut amount = [0u8; 16];
+            amount.copy_from_slice(&amounts[16 * i..16 * (i + 1)]);
+            u128::from_be_bytes(amount)
+        };
+
+        Ok(Input {
+            recursive_hash: hashes[..32].to_vec(),
+            num_inputs: bytes[0],
+            num_outputs: bytes[1],
+            nullifiers: hashes[32..].chunks(32).map(|n| n.to_vec()).collect(),
+            fee: amount(0),
+            min_output: amount(1),
+            nonce_range: NonceRange {
+                min: amount(2),
+                max: amount(3),
+            },
+            hash,
+        })
+    }
+
+    fn public_inputs<E: Engine>(&self) -> Vec<E::Fr> {
+        let mut inputs =
+            multipack::compute_multipacking::<E>(&multipack::bytes_to_bits(&self.shaped_hash()));
+        for nullifier in self.nullifiers.iter() {
+            inputs.extend(multipack::compute_multipacking::<E>(
+                &multipack::bytes_to_bits(nullifier),
+            ));
+        }
+        inputs.extend(multipack::compute_multipacking::<E>(
+            &multipack::bytes_to_bits(&self.fee.to_be_bytes()),
+        ));
+        for bound in [self.min_output, self.nonce_range.min, self.nonce_range.max].iter() {
+            inputs.extend(multipack::compute_multipacking::<E>(
+                &multipack::bytes_to_bits(&bound.to_be_bytes()),
+            ));
+        }
+
+        inputs
+    }
+}
+
+// Native counterpart of `Mixer::recursive_hash`, one note at a time. Feed it
+// the inputs then the outputs, in canonical order.
+#[derive(Debug, Clone, Default)]
+pub struct RecursiveHasher {
+    acc: Vec<u8>,
+    hash: HashFunction,
+}
+
+impl RecursiveHasher {
+    pub fn new() -> Self {
+        RecursiveHasher::default()
+    }
+
+    pub fn with_hash(hash: HashFunction) -> Self {
+        RecursiveHasher { acc: vec![], hash }
+    }
+
+    pub fn update(&mut self, amount: &Amount) {
+        self.update_note_hash(&amount.note_hash_with(self.hash));
+    }
+
+    fn update_note_hash(&mut self, note_hash: &[u8]) {
+        let mut preimage = MIXER_FOLD_DOMAIN.to_vec();
+        preimage.extend(&self.acc);
+        preimage.extend(note_hash);
+
+        self.acc = self.hash.digest(&preimage);
+    }
+
+    // All zeros if no note was hashed, which no mixer proof can have
+    pub fn finalize(self) -> [u8; 32] {
+        let mut digest = [0u8; 32];
+        if !self.acc.is_empty() {
+            digest.copy_from_slice(&self.acc);
+        }
+        digest
+    }
+}
+
+// One commitment over many proofs' public inputs, folded in the order given
+// as acc = sha256(domain | acc | input) with an empty first acc, each input
+// in its `to_hex` byte layout. Out of circuit only, so an aggregator can
+// publish a single root. All zeros for no inputs.
+pub fn aggregate_inputs(inputs: &[Input]) -> [u8; 32] {
+    let mut acc = vec![];
+    for input in inputs.iter() {
+        let mut preimage = MIXER_AGGREGATE_DOMAIN.to_vec();
+        preimage.extend(&acc);
+        preimage.extend(input.to_bytes());
+        acc = Sha256::digest(&preimage).to_vec();
+    }
+
+    let mut root = [0u8; 32];
+    if !acc.is_empty() {
+        root.copy_from_slice(&acc);
+    }
+    root
+}
+
+// Hash of each note under `hash`, in the order `Mixer::recursive_hash` folds
+// them
+pub fn note_hashes(amounts: &[Amount], hash: HashFunction) -> Vec<[u8; 32]> {
+    amounts
+        .iter()
+        .map(|amount| {
+            let mut note_hash = [0u8; 32];
+            note_hash.copy_from_slice(&amount.note_hash_with(hash));
+            note_hash
+        })
+        .collect()
+}
+
+// Takes the notes as given, so they must already be in the order
+// `Witness::canonicalize` puts them in. `Witness::to_input` sorts them itself.
+pub fn compute_public_input(
+    inputs: &[Amount],
+    outputs: &[Amount],
+    fee: u128,
+    min_output: u128,
+) -> Input {
+    compute_public_input_with_hash(inputs, outputs, fee, min_output, HashFunction::Sha256)
+}
+
+pub fn compute_public_input_with_hash(
+    inputs: &[Amount],
+    outputs: &[Amount],
+    fee: u128,
+    min_output: u128,
+    hash: HashFunction,
+) -> Input {
+    compute_public_input_with_nonce_range(
+        inputs,
+        outputs,
+        fee,
+        min_output,
+        NonceRange::default(),
+        hash,
+    )
+}
+
+pub fn compute_public_input_with_nonce_range(
+    inputs: &[Amount],
+    outputs: &[Amount],
+    fee: u128,
+    min_output: u128,
+    nonce_range: NonceRange,
+    hash: HashFunction,
+) -> Input {
+    // Folds what `note_hashes` reports, so the two can't disagree
+    let mut hasher = RecursiveHasher::with_hash(hash);
+    for note_hash in note_hashes(&[inputs, outputs].concat(), hash).iter() {
+        hasher.update_note_hash(note_hash);
+    }
+
+    Input {
+        recursive_hash: hasher.acc,
+        num_inputs: inputs.len() as u8,
+        num_outputs: outputs.len() as u8,
+        nullifiers: inputs.iter().map(Amount::nullifier).collect(),
+        fee,
+        min_output,
+        nonce_range,
+        hash,
+    }
+}
+
+struct VerifyingKey<E: Engine>(groth16::VerifyingKey<E>);
+
+impl<E: Engine> VerifyingKey<E> {
+    fn from_bytes(bytes: &[u8]) -> Result<VerifyingKey<E>, std::io::Error> {
+        // bellman allocates as many IC points as the count claims before
+        // reading any, so check they are all there first
+        let header = verifying_key_header_bytes::<E>();
+        let ic_len = bytes
+            .get(header - 4..header)
+            .map(|len| u32::from_be_bytes([len[0], len[1], len[2], len[3]]) as usize);
+        let enough = ic_len
+            .and_then(|ic_len| ic_len.checked_mul(g1_bytes::<E>()))
+            .and_then(|ic_bytes| ic_bytes.checked_add(header))
+            .is_some_and(|len| bytes.len() >= len);
+        if !enough {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "verifying key shorter than its IC points",
+            ));
+        }
+
+        let k = groth16::VerifyingKey::read(bytes)?;
+        Ok(VerifyingKey(k))
+    }
+}
+
+// Parses a verifying key without ever panicking, for fuzzing a verifier.
+// Every point is decoded, which checks it is on the curve and in the prime
+// order subgroup, and a panic anywhere in bellman is returned as an error.
+pub fn try_parse_vk(bytes: &[u8]) -> Result<(), VerifyError> {
+    std::panic::catch_unwind(|| VerifyingKey::<Bls12>::from_bytes(bytes))
+        .unwrap_or_else(|_| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "verifying key parser panicked",
+            ))
+        })
+        .map(|_| ())
+        .map_err(VerifyError::VerifyingKey)
+}
+
+// Prepares the verifying key once so it can be reused across many proofs
+pub struct PreparedVerifier(groth16::PreparedVerifyingKey<Bls12>);
+
+impl PreparedVerifier {
+    pub fn from_bytes(vk_bytes: &[u8]) -> Result<PreparedVerifier, VerifyError> {
+        let verifying_key =
+            VerifyingKey::<Bls12>::from_bytes(vk_bytes).map_err(VerifyError::VerifyingKey)?;
+
+        Ok(PreparedVerifier(verify_core::prepare(&verifying_key.0)))
+    }
+
+    pub fn verify(&self, proof: &[u8], input: Input) -> Result<bool, VerifyError> {
+        self.verify_with_inputs(proof, &input.public_inputs::<Bls12>())
+    }
+
+    pub fn verify_with_inputs(&self, proof: &[u8], inputs: &[Fr]) -> Result<bool, VerifyError> {
+        let proof = Proof::<Bls12>::from_bytes(proof).map_err(VerifyError::Proof)?;
+
+        self.verify_parsed(&proof, inputs)
+    }
+
+    // `verify_core::verify` against the prepared key, for an already decoded
+    // proof
+    pub fn verify_parsed(&self, proof: &Proof, inputs: &[Fr]) -> Result<bool, VerifyError> {
+        Ok(verify_core::verify(&self.0, &proof.0, inputs)?)
+    }
+}
+
+pub fn verify(vk_bytes: &[u8], proof: &[u8], input: Input) -> Result<bool, VerifyError> {
+    PreparedVerifier::from_bytes(vk_bytes)?.verify(proof, input)
+}
+
+// Same as `verify` for callers that already hold the packed public inputs,
+// in the order `verify_verbose` reports them
+pub fn verify_with_inputs(
+    vk_bytes: &[u8],
+    proof: &[u8],
+    inputs: &[Fr],
+) -> Result<bool, VerifyError> {
+    PreparedVerifier::from_bytes(vk_bytes)?.verify_with_inputs(proof, inputs)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifyReport {
+    // Packed field elements checked against the proof, in circuit order
+    pub public_inputs: Vec<Fr>,
+    pub verified: bool,
+}
+
+// Same as `verify`, but returns the public inputs it reconstructed so they can
+// be compared with what the prover packed
+pub fn verify_verbose(
+    vk_bytes: &[u8],
+    proof: &[u8],
+    input: Input,
+) -> Result<VerifyReport, VerifyError> {
+    let verifier = PreparedVerifier::from_bytes(vk_bytes)?;
+    let public_inputs = input.public_inputs::<Bls12>();
+
+    let proof = Proof::<Bls12>::from_bytes(proof).map_err(VerifyError::Proof)?;
+
+    let verified = verifier.verify_parsed(&proof, &public_inputs)?;
+
+    Ok(VerifyReport {
+        public_inputs,
+        verified,
+    })
+}
+
+// Same answer as `verify`, but a proof that fails to parse still goes through
+// the pairing check, against the generators, so it takes as long as a
+// well-formed proof that fails. The key is public and isn't treated this way.
+pub fn verify_ct(vk_bytes: &[u8], proof: &[u8], input: Input) -> Choice {
+    let verifier = match PreparedVerifier::from_bytes(vk_bytes) {
+        Ok(verifier) => verifier,
+        Err(_) => return Choice::from(0),
+    };
+    let inputs = input.public_inputs::<Bls12>();
+
+    let (parsed, proof) = match Proof::<Bls12>::from_bytes(proof) {
+        Ok(proof) => (Choice::from(1), proof),
+        Err(_) => (
+            Choice::from(0),
+            Proof(groth16::Proof {
+                a: G1Affine::one(),
+                b: G2Affine::one(),
+                c: G1Affine::one(),
+            }),
+        ),
+    };
+
+    let verified = verifier.verify_parsed(&proof, &inputs).unwrap_or(false);
+    parsed & Choice::from(verified as u8)
+}
+
+pub fn verify_for<E: Engine>(
+    vk_bytes: &[u8],
+    proof: &[u8],
+    input: Input,
+) -> Result<bool, VerifyError> {
+    let verifying_key =
+        VerifyingKey::<E>::from_bytes(vk_bytes).map_err(VerifyError::VerifyingKey)?;
+    let verifying_key = verify_core::prepare(&verifying_key.0);
+
+    let inputs = input.public_inputs::<E>();
+
+    let proof = Proof::<E>::from_bytes(proof).map_err(VerifyError::Proof)?;
+
+    Ok(verify_core::verify(&verifying_key, &proof.0, &inputs)?)
+}
+
+// Returns the index of the first verifying key accepting the proof, skipping
+// keys that fail to parse. Useful while rotating keys.
+pub fn verify_any(vks: &[Vec<u8>], proof: &[u8], input: Input) -> Option<usize> {
+    let inputs = input.public_inputs::<Bls12>();
+
+    let proof = Proof::<Bls12>::from_bytes(proof).ok()?;
+
+    vks.iter().position(
+        |vk_bytes| match VerifyingKey::<Bls12>::from_bytes(vk_bytes) {
+            Ok(verifying_key) => {
+                let verifying_key = verify_core::prepare(&verifying_key.0);
+                verify_core::verify(&verifying_key, &proof.0, &inputs).unwrap_or(false)
+            }
+            Err(_) => false,
+        },
+    )
+}
+
+// Checks every proof at once with a single multi-miller loop over a random
+// linear combination of the groth16 equations. If the combined check fails
+// the proofs are verified one by one so callers learn which ones are bad.
+pub fn verify_batch(
+    vk_bytes: &[u8],
+    proofs: &[(Vec<u8>, Input)],
+) -> Result<Vec<bool>, VerifyError> {
+    let vk = VerifyingKey::<Bls12>::from_bytes(vk_bytes)
+        .map_err(VerifyError::VerifyingKey)?
+        .0;
+
+    let parsed = proofs
+        .iter()
+        .map(|(proof, input)| {
+            let proof = Proof::<Bls12>::from_bytes(proof).map_err(VerifyError::Proof)?;
+            let inputs = input.public_inputs::<Bls12>();
+            if inputs.len() + 1 != vk.ic.len() {
+                return Err(VerifyError::Verification(
+                    SynthesisError::MalformedVerifyingKey,
+                ));
+            }
+            Ok((proof, inputs))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // coeffs[0] is the sum of the randomizers, coeffs[i + 1] the randomized
+    // sum of the i-th public input across all proofs
+    let mut coeffs = vec![Fr::zero(); vk.ic.len()];
+    let mut c_sum = G1::zero();
+    let mut ab = Vec::with_capacity(parsed.len());
+    for (Proof(proof), inputs) in parsed.iter() {
+        let r = Fr::random(&mut OsRng);
+        coeffs[0].add_assign(&r);
+        for (coeff, input) in coeffs[1..].iter_mut().zip(inputs.iter()) {
+            let mut term = *input;
+            term.mul_assign(&r);
+            coeff.add_assign(&term);
+        }
+
+        c_sum.add_assign(&proof.c.mul(r.into_repr()));
+        ab.push((
+            proof.a.mul(r.into_repr()).into_affine().prepare(),
+            proof.b.prepare(),
+        ));
+    }
+
+    let mut acc_sum = G1::zero();
+    for (ic, coeff) in vk.ic.iter().zip(coeffs.iter()) {
+        acc_sum.add_assign(&ic.mul(coeff.into_repr()));
+    }
+    let mut alpha_sum = vk.alpha_g1.mul(coeffs[0].into_repr());
+
+    acc_sum.negate();
+    c_sum.negate();
+    alpha_sum.negate();
+    let neg_acc = acc_sum.into_affine().prepare();
+    let neg_c = c_sum.into_affine().prepare();
+    let neg_alpha = alpha_sum.into_affine().prepare();
+    let gamma = vk.gamma_g2.prepare();
+    let delta = vk.delta_g2.prepare();
+    let beta = vk.beta_g2.prepare();
+
+    let mut terms = ab.iter().map(|(a, b)| (a, b)).collect::<Vec<_>>();
+    terms.push((&neg_acc, &gamma));
+    terms.push((&neg_c, &delta));
+    terms.push((&neg_alpha, &beta));
+
+    let batched = Bls12::final_exponentiation(&Bls12::miller_loop(terms.iter()))
+        .is_some_and(|f| f == Fq12::one());
+    if batched {
+        return Ok(vec![true; parsed.len()]);
+    }
+
+    let pvk = verify_core::prepare(&vk);
+    parsed
+        .iter()
+        .map(|(proof, inputs)| Ok(verify_core::verify(&pvk, &proof.0, inputs)?))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Preimage of an unblinded note whose value fits in the low limb
+    fn combine(amount: u128, nonce: u128) -> [u8; 64] {
+        let amount_bytes = amount.to_be_bytes();
+        let nonce_bytes = nonce.to_be_bytes();
+
+        let mut bytes = [0u8; 64];
+        bytes[16..32].copy_from_slice(&amount_bytes);
+        bytes[32..48].copy_from_slice(&nonce_bytes);
+
+        bytes
+    }
+
+    // One note in and one out, no fee, full nonce range and SHA256. Tests
+    // override the fields they exercise.
+    fn witness() -> Witness {
+        Witness {
+            inputs: vec![Amount::new(Value(1), Nonce(1))],
+            outputs: vec![Amount::new(Value(1), Nonce(2))],
+            fee: 0,
+            min_output: 0,
+            nonce_range: NonceRange::default(),
+            hash: HashFunction::Sha256,
+        }
+    }
+
+    #[test]
+    fn basic_test() {
+        let params = trust_setup(2, 1); // Support up to 2 inputs and 1 outputs
+        println!("complete trust setup");
+
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(1), Nonce(1)),
+                Amount::new(Value(2), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(3), Nonce(2))],
+            ..witness()
+        };
+
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+        println!("complete generate proof");
+
+        let amounts = vec![combine(1, 1), combine(2, 2), combine(3, 2)];
+
+        let amount_hashes = amounts
+            .into_iter()
+            .map(|a| {
+                let mut preimage = b"mixer/note/hash/".to_vec();
+                preimage.extend(&a);
+                Sha256::digest(&preimage).to_vec()
+            })
+            .collect::<Vec<_>>();
+
+        let recursive_hash = amount_hashes.into_iter().fold(Vec::new(), |acc, h| {
+            let mut preimage = b"mixer/recursive/".to_vec();
+            preimage.extend(acc);
+            preimage.extend(h);
+            Sha256::digest(&preimage).to_vec()
+        });
+
+        let nullifiers = vec![combine(1, 1).to_vec(), combine(2, 2).to_vec()]
+            .into_iter()
+            .map(|mut a| {
+                a.extend(b"mixer/nullifier/");
+                Sha256::digest(&a).to_vec()
+            })
+            .collect();
+
+        let input = Input {
+            recursive_hash,
+            num_inputs: 2,
+            num_outputs: 1,
+            nullifiers,
+            fee: 0,
+            min_output: 0,
+            nonce_range: NonceRange::default(),
+            hash: HashFunction::Sha256,
+        };
+        println!("complete input");
+
+        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input).expect("verify"))
+    }
+
+    #[test]
+    fn verify_rejects_malformed_bytes() {
+        let params = trust_setup(1, 1);
+        let witness = witness();
+
+        let vk = params.verifying_key();
+        let proof = generate_proof(witness, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let input = || Input {
+            recursive_hash: vec![0u8; 32],
+            num_inputs: 1,
+            num_outputs: 1,
+            nullifiers: vec![vec![0u8; 32]],
+            fee: 0,
+            min_output: 0,
+            nonce_range: NonceRange::default(),
+            hash: HashFunction::Sha256,
+        };
+
+        match verify(&vk[..vk.len() / 2], &proof, input()) {
+            Err(VerifyError::VerifyingKey(_)) => (),
+            _ => panic!("expected verifying key error"),
+        }
+
+        match verify(&vk, &proof[..proof.len() - 1], input()) {
+            Err(VerifyError::Proof(_)) => (),
+            _ => panic!("expected proof error"),
+        }
+    }
+
+    #[test]
+    fn mixer_chain_conserves_value() {
+        let params = trust_setup(1, 1).to_bytes();
+
+        let steps = vec![
+            Witness {
+                inputs: vec![Amount::new(Value(3), Nonce(1))],
+                outputs: vec![Amount::new(Value(3), Nonce(2))],
+                ..witness()
+            },
+            Witness {
+                inputs: vec![Amount::new(Value(3), Nonce(2))],
+                outputs: vec![Amount::new(Value(3), Nonce(3))],
+                ..witness()
+            },
+        ];
+
+        let (proofs, report) = prove_mixer_chain(&steps, &params).expect("conserving chain");
+        assert_eq!(proofs.len(), 2);
+        assert_eq!(report.total_inputs, U256::from(3));
+        assert_eq!(report.total_outputs, U256::from(3));
+
+        let leaking = vec![
+            Witness {
+                inputs: vec![Amount::new(Value(5), Nonce(1))],
+                outputs: vec![Amount::new(Value(3), Nonce(2))],
+                ..witness()
+            },
+            Witness {
+                inputs: vec![Amount::new(Value(3), Nonce(2))],
+                outputs: vec![Amount::new(Value(3), Nonce(3))],
+                ..witness()
+            },
+        ];
+
+        match prove_mixer_chain(&leaking, &params) {
+            Err(ChainError::NotConserved { inputs, outputs }) => {
+                assert_eq!((inputs, outputs), (U256::from(5), U256::from(3)))
+            }
+            _ => panic!("expected leaked value"),
+        }
+    }
+
+    #[test]
+    fn amount_from_typed_arguments() {
+        let amount = Amount::new(Value(5), Nonce(7));
+        assert_eq!((amount.value, amount.nonce), (5, 7));
+
+        let amount = Amount::new(5.into(), 7.into());
+        assert_eq!(amount, Amount::new(Value(5), Nonce(7)));
+    }
+
+    #[test]
+    fn verify_with_computed_public_input() {
+        let params = trust_setup(2, 1);
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(5), Nonce(1)),
+                Amount::new(Value(6), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(11), Nonce(3))],
+            ..witness()
+        };
+
+        let input = compute_public_input(
+            &witness.inputs,
+            &witness.outputs,
+            witness.fee,
+            witness.min_output,
+        );
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+
+        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input).expect("verify"))
+    }
+
+    #[test]
+    fn canonical_witness_order() {
+        let params = trust_setup(2, 1);
+
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(2), Nonce(20)),
+                Amount::new(Value(1), Nonce(10)),
+            ],
+            outputs: vec![Amount::new(Value(3), Nonce(30))],
+            ..witness()
+        };
+        let reordered = Witness {
+            inputs: witness.inputs.iter().rev().cloned().collect(),
+            outputs: witness.outputs.clone(),
+            ..witness.clone()
+        };
+        assert_ne!(witness.inputs, reordered.inputs);
+
+        let mut canonical = witness.clone();
+        canonical.canonicalize();
+        assert_eq!(canonical.inputs, reordered.inputs);
+
+        let input = witness.to_input();
+        assert_eq!(input.recursive_hash, reordered.to_input().recursive_hash);
+
+        // Neither is canonicalized before proving
+        let vk = params.verifying_key();
+        for w in [witness, reordered] {
+            let proof = generate_proof(w.clone(), &params.to_bytes()).expect("prove");
+            assert!(verify(&vk, &proof.to_bytes(), w.to_input()).expect("verify"));
+        }
+    }
+
+    #[test]
+    fn canonical_order_covers_blinding() {
+        let note = Amount::new(Value(2), Nonce(1));
+        let witness = Witness {
+            inputs: vec![
+                note.with_blinding(Blinding(9)),
+                note.with_blinding(Blinding(3)),
+            ],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let mut reordered = witness.clone();
+        reordered.inputs.reverse();
+
+        let mut canonical = witness.clone();
+        canonical.canonicalize();
+        assert_eq!(canonical.inputs, reordered.inputs);
+        assert_eq!(
+            witness.to_input().recursive_hash,
+            reordered.to_input().recursive_hash
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn witness_json_round_trip() {
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(u128::MAX), Nonce(u128::MAX))],
+            outputs: vec![Amount::new(Value(1), Nonce(2))],
+            ..witness()
+        };
+
+        let json = serde_json::to_string(&witness).expect("serialize");
+        assert!(json.contains(&format!("\"{}\"", u128::MAX)));
+
+        let decoded: Witness = serde_json::from_str(&json).expect("deserialize");
+        assert_eq!(decoded.inputs, witness.inputs);
+        assert_eq!(decoded.outputs, witness.outputs);
+    }
+
+    #[test]
+    fn verify_rejects_mismatched_shape() {
+        let params = trust_setup(2, 1);
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(1), Nonce(1)),
+                Amount::new(Value(2), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(3), Nonce(3))],
+            ..witness()
+        };
+
+        let input = compute_public_input(
+            &witness.inputs,
+            &witness.outputs,
+            witness.fee,
+            witness.min_output,
+        );
+        let proof = generate_proof(witness, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+
+        let reshaped = Input {
+            recursive_hash: input.recursive_hash.clone(),
+            num_inputs: 1,
+            num_outputs: 2,
+            nullifiers: input.nullifiers.clone(),
+            fee: 0,
+            min_output: 0,
+            nonce_range: NonceRange::default(),
+            hash: HashFunction::Sha256,
+        };
+
+        let vk = params.verifying_key();
+        assert!(verify(&vk, &proof, input).expect("verify"));
+        assert!(!verify(&vk, &proof, reshaped).expect("verify"));
+    }
+
+    #[test]
+    fn verify_any_finds_rotated_key() {
+        let old_params = trust_setup(1, 1);
+        let new_params = trust_setup(1, 1);
+        let witness = witness();
+
+        let input = || {
+            compute_public_input(
+                &witness.inputs,
+                &witness.outputs,
+                witness.fee,
+                witness.min_output,
+            )
+        };
+        let proof = generate_proof(witness.clone(), &new_params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+
+        let vks = vec![old_params.verifying_key(), new_params.verifying_key()];
+        assert_eq!(verify_any(&vks, &proof, input()), Some(1));
+        assert_eq!(verify_any(&vks[..1], &proof, input()), None);
+    }
+
+    #[test]
+    fn amounts_sort_by_value_then_nonce() {
+        let mut amounts = vec![
+            Amount::new(Value(3), Nonce(1)),
+            Amount::new(Value(1), Nonce(9)),
+            Amount::new(Value(3), Nonce(0)),
+            Amount::new(Value(2), Nonce(5)),
+        ];
+        amounts.sort();
+
+        assert_eq!(
+            amounts,
+            vec![
+                Amount::new(Value(1), Nonce(9)),
+                Amount::new(Value(2), Nonce(5)),
+                Amount::new(Value(3), Nonce(0)),
+                Amount::new(Value(3), Nonce(1)),
+            ]
+        );
+
+        let set = amounts
+            .iter()
+            .cloned()
+            .collect::<std::collections::BTreeSet<_>>();
+        assert_eq!(
+            set.into_iter().next(),
+            Some(Amount::new(Value(1), Nonce(9)))
+        );
+    }
+
+    #[test]
+    fn spending_a_note_twice_reveals_its_nullifier() {
+        let params = trust_setup(1, 1);
+        let note = Amount::new(Value(5), Nonce(1));
+        let first = Witness {
+            inputs: vec![note],
+            outputs: vec![Amount::new(Value(5), Nonce(2))],
+            ..witness()
+        };
+        let second = Witness {
+            inputs: vec![note],
+            outputs: vec![Amount::new(Value(5), Nonce(3))],
+            ..witness()
+        };
+
+        let first_input = first.to_input();
+        let second_input = second.to_input();
+        assert_eq!(first_input.nullifiers(), second_input.nullifiers());
+        assert_ne!(
+            first_input.nullifiers()[0],
+            Sha256::digest(&combine(5, 1)).to_vec()
+        );
+
+        let vk = params.verifying_key();
+        let first_proof = generate_proof(first, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let second_proof = generate_proof(second, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        assert!(verify(&vk, &first_proof, first_input).expect("verify"));
+        assert!(verify(&vk, &second_proof, second_input).expect("verify"));
+    }
+
+    #[test]
+    fn generate_proof_rejects_truncated_params() {
+        let params = trust_setup(1, 1).to_bytes();
+        let witness = witness();
+
+        match generate_proof(witness, &params[..params.len() / 2]) {
+            Err(ProveError::Params(_)) => (),
+            _ => panic!("expected params error"),
+        }
+    }
+
+    #[test]
+    fn generate_proof_rejects_witness_of_other_shape() {
+        let params = trust_setup(2, 1);
+        assert_eq!(params.shape(), (2, 1));
+        let params = params.to_bytes();
+        assert_eq!(
+            Params::<Bls12>::from_bytes(&params).expect("read").shape(),
+            (2, 1)
+        );
+
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(1), Nonce(1)),
+                Amount::new(Value(1), Nonce(2)),
+                Amount::new(Value(1), Nonce(3)),
+            ],
+            outputs: vec![Amount::new(Value(3), Nonce(4))],
+            ..witness()
+        };
+        match generate_proof(witness, &params) {
+            Err(ProveError::Shape {
+                params: (2, 1),
+                witness: (3, 1),
+            }) => (),
+            other => panic!("expected shape error, got {:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn verify_batch_detects_invalid_proof() {
+        let params = trust_setup(1, 1);
+        let witnesses = (1..4)
+            .map(|i| Witness {
+                inputs: vec![Amount::new(Value(i), Nonce(i))],
+                outputs: vec![Amount::new(Value(i), Nonce(i + 10))],
+                ..witness()
+            })
+            .collect::<Vec<_>>();
+
+        let batch = || {
+            witnesses
+                .iter()
+                .map(|w| {
+                    let proof = generate_proof(w.clone(), &params.to_bytes()).expect("prove");
+                    (proof.to_bytes(), w.to_input())
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let vk = params.verifying_key();
+        assert_eq!(verify_batch(&vk, &batch()).expect("verify"), vec![true; 3]);
+
+        let mut tampered = batch();
+        tampered[1].1 = witnesses[2].to_input();
+        assert_eq!(
+            verify_batch(&vk, &tampered).expect("verify"),
+            vec![true, false, true]
+        );
+    }
+
+    #[test]
+    fn prepared_verifier_reuses_key() {
+        let params = trust_setup(1, 1);
+        let witnesses = (1..4)
+            .map(|i| Witness {
+                inputs: vec![Amount::new(Value(i), Nonce(i))],
+                outputs: vec![Amount::new(Value(i), Nonce(i + 10))],
+                ..witness()
+            })
+            .collect::<Vec<_>>();
+        let proofs = witnesses
+            .iter()
+            .map(|w| {
+                generate_proof(w.clone(), &params.to_bytes())
+                    .expect("prove")
+                    .to_bytes()
+            })
+            .collect::<Vec<_>>();
+
+        let vk = params.verifying_key();
+        let verifier = PreparedVerifier::from_bytes(&vk).expect("prepare");
+
+        // The key is prepared once above, every later call reuses it and
+        // must keep agreeing with `verify`, which prepares it afresh
+        for _ in 0..3 {
+            for (i, proof) in proofs.iter().enumerate() {
+                for (j, w) in witnesses.iter().enumerate() {
+                    let verified = verifier.verify(proof, w.to_input()).expect("verify");
+                    assert_eq!(verified, i == j);
+                    assert_eq!(verified, verify(&vk, proof, w.to_input()).expect("verify"));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn fee_is_committed_as_public_input() {
+        let params = trust_setup(1, 1);
+        let witness = |fee| Witness {
+            inputs: vec![Amount::new(Value(10), Nonce(1))],
+            outputs: vec![Amount::new(Value(7), Nonce(2))],
+            fee,
+            ..witness()
+        };
+
+        let proof = generate_proof(witness(3), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+
+        let vk = params.verifying_key();
+        assert_eq!(witness(3).to_input().fee(), 3);
+        assert!(verify(&vk, &proof, witness(3).to_input()).expect("verify"));
+        assert!(!verify(&vk, &proof, witness(2).to_input()).expect("verify"));
+
+        match generate_proof(witness(2), &params.to_bytes()) {
+            Err(ProveError::Synthesis(SynthesisError::Unsatisfiable)) => (),
+            _ => panic!("expected unsatisfiable fee"),
+        }
+    }
+
+    #[test]
+    fn params_save_and_load() {
+        let path = std::env::temp_dir().join("mixer-params-save-and-load.params");
+        let params = trust_setup(1, 1);
+        params.save(&path).expect("save");
+
+        let loaded = Params::<Bls12>::load(&path).expect("load");
+        std::fs::remove_file(&path).expect("remove");
+        assert_eq!(loaded.to_bytes(), params.to_bytes());
+
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness.clone(), &loaded.to_bytes()).expect("prove");
+        assert!(verify(
+            &params.verifying_key(),
+            &proof.to_bytes(),
+            witness.to_input()
+        )
+        .expect("verify"));
+    }
+
+    #[test]
+    fn verifying_key_from_params_bytes_matches_params() {
+        let params = trust_setup(1, 1);
+        let params_bytes = params.to_bytes();
+
+        let vk = verifying_key_from_params_bytes(&params_bytes).expect("extract vk");
+        assert_eq!(vk, params.verifying_key());
+        assert!(verifying_key_from_params_bytes(&params_bytes[..vk.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn serialized_sizes_match_constants() {
+        for &(inputs_size, outputs_size) in &[(1, 1), (2, 3)] {
+            let params = trust_setup(inputs_size, outputs_size);
+            assert_eq!(
+                params.verifying_key().len(),
+                verifying_key_size(inputs_size, outputs_size)
+            );
+        }
+
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let params = trust_setup(1, 1);
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+        assert_eq!(proof.to_bytes().len(), PROOF_SIZE_BYTES);
+    }
+
+    #[test]
+    fn params_and_proof_from_reader() {
+        let path = std::env::temp_dir().join("mixer-params-from-reader.params");
+        let params = trust_setup(1, 1);
+        std::fs::write(&path, params.to_bytes()).expect("write");
+
+        let file = File::open(&path).expect("open");
+        let loaded = Params::<Bls12>::from_reader(BufReader::new(file)).expect("read params");
+        std::fs::remove_file(&path).expect("remove");
+        assert_eq!(loaded.to_bytes(), params.to_bytes());
+
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness, &loaded.to_bytes()).expect("prove");
+        let proof_bytes = proof.to_bytes();
+        let read =
+            Proof::<Bls12>::from_reader(BufReader::new(&proof_bytes[..])).expect("read proof");
+        assert_eq!(read.to_bytes(), proof_bytes);
+    }
+
+    #[test]
+    fn duplicate_inputs_are_rejected() {
+        let params = trust_setup(2, 1);
+        let note = Amount::new(Value(2), Nonce(1));
+        let witness = Witness {
+            inputs: vec![note, note],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+
+        let proof = generate_proof(witness.clone(), &params.to_bytes()).expect("prove");
+        assert!(!verify(
+            &params.verifying_key(),
+            &proof.to_bytes(),
+            witness.to_input()
+        )
+        .expect("verify"));
+    }
+
+    fn prove_and_verify_for<E: Engine>(witness: Witness) -> bool {
+        let params = trust_setup_for::<E>(1, 1);
+        let proof = generate_proof_for::<E>(witness.clone(), &params.to_bytes()).expect("prove");
+
+        verify_for::<E>(
+            &params.verifying_key(),
+            &proof.to_bytes(),
+            witness.to_input(),
+        )
+        .expect("verify")
+    }
+
+    #[test]
+    fn generic_engine_pipeline() {
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(6), Nonce(1))],
+            outputs: vec![Amount::new(Value(6), Nonce(2))],
+            ..witness()
+        };
+
+        assert!(prove_and_verify_for::<Bls12>(witness));
+    }
+
+    #[test]
+    fn verify_ct_matches_verify() {
+        let params = trust_setup(1, 1);
+        let vk = params.verifying_key();
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(3), Nonce(1))],
+            outputs: vec![Amount::new(Value(3), Nonce(2))],
+            ..witness()
+        };
+        let other = Witness {
+            inputs: vec![Amount::new(Value(3), Nonce(4))],
+            outputs: vec![Amount::new(Value(3), Nonce(5))],
+            ..witness.clone()
+        };
+        let proof = generate_proof(witness.clone(), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+
+        for w in [&witness, &other].iter() {
+            let expected = verify(&vk, &proof, w.to_input()).expect("verify");
+            assert_eq!(bool::from(verify_ct(&vk, &proof, w.to_input())), expected);
+        }
+        assert!(bool::from(verify_ct(&vk, &proof, witness.to_input())));
+
+        let malformed = &proof[..proof.len() - 1];
+        assert!(!bool::from(verify_ct(&vk, malformed, witness.to_input())));
+    }
+
+    #[test]
+    fn streaming_recursive_hash_matches_batch() {
+        let inputs = vec![
+            Amount::new(Value(5), Nonce(1)),
+            Amount::new(Value(6), Nonce(2)),
+        ];
+        let outputs = vec![Amount::new(Value(11), Nonce(3))];
+
+        let mut hasher = RecursiveHasher::new();
+        for amount in inputs.iter().chain(outputs.iter()) {
+            hasher.update(amount);
+        }
+
+        let input = compute_public_input(&inputs, &outputs, 0, 0);
+        assert_eq!(hasher.finalize().to_vec(), input.recursive_hash);
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn proof_and_verifying_key_json_round_trip() {
+        let params = trust_setup(1, 1);
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(2), Nonce(1))],
+            outputs: vec![Amount::new(Value(2), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+
+        let json = proof.to_json();
+        assert!(json.contains("\"scheme\":\"groth16-bls12-381\""));
+        let decoded = Proof::<Bls12>::from_json(&json).expect("decode proof");
+        assert_eq!(decoded.to_bytes(), proof.to_bytes());
+
+        let vk = params.verifying_key();
+        let json = verifying_key_to_json(&vk);
+        assert_eq!(verifying_key_from_json(&json).expect("decode vk"), vk);
+
+        let other_curve = json.replace("bls12-381", "bn254");
+        match verifying_key_from_json(&other_curve) {
+            Err(JsonError::Scheme(scheme)) => assert_eq!(scheme, "groth16-bn254"),
+            _ => panic!("expected scheme error"),
+        }
+    }
+
+    #[test]
+    fn constraint_count_grows_with_shape() {
+        let base = constraint_count(1, 1);
+        assert!(base > 0);
+        assert!(constraint_count(2, 1) > base);
+        assert!(constraint_count(1, 2) > base);
+        assert!(constraint_count(2, 2) > constraint_count(2, 1));
+    }
+
+    #[test]
+    fn blinding_changes_note_hash() {
+        let note = Amount::new(Value(7), Nonce(1));
+        let blinded = note.with_blinding(Blinding(99));
+        assert_ne!(note.note_hash(), blinded.note_hash());
+        assert_ne!(note.nullifier(), blinded.nullifier());
+
+        let params = trust_setup(1, 1);
+        let vk = params.verifying_key();
+        let witness = Witness {
+            inputs: vec![blinded],
+            outputs: vec![Amount::new(Value(7), Nonce(2)).with_blinding(Blinding(5))],
+            ..witness()
+        };
+        let proof = generate_proof(witness.clone(), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        assert!(verify(&vk, &proof, witness.to_input()).expect("verify"));
+
+        let mut unblinded = witness;
+        unblinded.inputs = vec![note];
+        assert!(!verify(&vk, &proof, unblinded.to_input()).expect("verify"));
+    }
+
+    #[test]
+    fn note_hashes_fold_into_recursive_hash() {
+        let inputs = vec![
+            Amount::new(Value(1), Nonce(1)),
+            Amount::new(Value(2), Nonce(2)),
+        ];
+        let outputs = vec![Amount::new(Value(3), Nonce(3))];
+
+        let notes = [inputs.clone(), outputs.clone()].concat();
+        let hashes = note_hashes(&notes, HashFunction::Sha256);
+        assert_eq!(hashes.len(), 3);
+        assert_eq!(hashes[2].to_vec(), outputs[0].note_hash());
+        assert_eq!(
+            note_hashes(&notes, HashFunction::Blake2s)[2].to_vec(),
+            outputs[0].note_hash_with(HashFunction::Blake2s)
+        );
+
+        let recursive_hash = hashes.iter().fold(Vec::new(), |acc, h| {
+            let mut preimage = MIXER_FOLD_DOMAIN.to_vec();
+            preimage.extend(acc);
+            preimage.extend(h);
+            Sha256::digest(&preimage).to_vec()
         });
+        assert_eq!(
+            compute_public_input(&inputs, &outputs, 0, 0).recursive_hash,
+            recursive_hash
+        );
+    }
 
-        let input = Input { recursive_hash };
-        println!("complete input");
+    #[test]
+    fn params_cache_sets_up_each_shape_once() {
+        let cache = ParamsCache::new();
+        assert!(cache.is_empty());
+
+        let first = cache.get_or_setup(1, 1);
+        let second = cache.get_or_setup(1, 1);
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(first.to_bytes(), second.to_bytes());
+        assert_eq!(cache.len(), 1);
+
+        let other = cache.get_or_setup(2, 1);
+        assert_ne!(other.verifying_key(), first.verifying_key());
+        assert_eq!(cache.len(), 2);
+    }
+
+    #[test]
+    fn check_witness_without_proving() {
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(3), Nonce(2))],
+            fee: 1,
+            ..witness()
+        };
+        check_witness(&witness).expect("valid witness");
+
+        let mut overspent = witness.clone();
+        overspent.outputs = vec![Amount::new(Value(5), Nonce(2))];
+        match check_witness(&overspent) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            other => panic!("expected unsatisfiable, got {:?}", other),
+        }
+
+        // Balances, but only by spending the same note twice
+        let double_spent = Witness {
+            inputs: vec![witness.inputs[0], witness.inputs[0]],
+            outputs: vec![Amount::new(Value(8), Nonce(2))],
+            fee: 0,
+            ..witness.clone()
+        };
+        match check_witness(&double_spent) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            other => panic!("expected unsatisfiable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn input_hex_round_trip() {
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(5), Nonce(1)),
+                Amount::new(Value(6), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(10), Nonce(3))],
+            fee: 1,
+            ..witness()
+        };
+        let input = witness.to_input();
+
+        let decoded = Input::from_hex(&input.to_hex()).expect("valid hex");
+        assert_eq!(decoded.to_hex(), input.to_hex());
+        assert_eq!(decoded.nullifiers(), input.nullifiers());
+        assert_eq!(decoded.fee(), 1);
+
+        assert!(Input::from_hex("not hex").is_err());
+        let truncated = &input.to_hex()[..input.to_hex().len() - 2];
+        assert_eq!(
+            Input::from_hex(truncated).err(),
+            Some(InputHexError::Hex(hex::FromHexError::InvalidStringLength))
+        );
+        let unknown_hash = format!("{}07", truncated);
+        assert_eq!(
+            Input::from_hex(&unknown_hash).err(),
+            Some(InputHexError::UnknownHash(7))
+        );
+    }
+
+    #[test]
+    fn seeded_proof_is_reproducible() {
+        let params = trust_setup(1, 1).to_bytes();
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+
+        let proof = generate_proof_seeded(witness.clone(), &params, [7u8; 32])
+            .expect("prove")
+            .to_bytes();
+        let again = generate_proof_seeded(witness.clone(), &params, [7u8; 32])
+            .expect("prove")
+            .to_bytes();
+        assert_eq!(proof, again);
+
+        let other_seed = generate_proof_seeded(witness, &params, [8u8; 32])
+            .expect("prove")
+            .to_bytes();
+        assert_ne!(proof, other_seed);
+    }
+
+    #[test]
+    fn compressed_and_uncompressed_encodings() {
+        let params = trust_setup(1, 1);
+        let compressed = params.to_bytes_compressed();
+        assert!(compressed.len() < params.to_bytes().len());
+
+        let decoded = Params::<Bls12>::from_bytes_compressed(&compressed).expect("read params");
+        assert_eq!(decoded.to_bytes(), params.to_bytes());
+        assert!(
+            Params::<Bls12>::from_bytes_compressed(&compressed[..compressed.len() - 1]).is_err()
+        );
+
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let input = witness.to_input();
+        let proof = generate_proof(witness, &decoded.to_bytes()).expect("prove");
+
+        let uncompressed = proof.to_bytes_uncompressed();
+        assert!(proof.to_bytes().len() < uncompressed.len());
+        let decoded = Proof::<Bls12>::from_bytes_uncompressed(&uncompressed).expect("read proof");
+        assert_eq!(decoded.to_bytes(), proof.to_bytes());
+        assert!(verify(&params.verifying_key(), &decoded.to_bytes(), input).expect("verify"));
+    }
+
+    #[test]
+    fn rerandomized_proof_still_verifies() {
+        let params = trust_setup(1, 1);
+        let vk = params.verifying_key();
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(4), Nonce(1))],
+            outputs: vec![Amount::new(Value(4), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness.clone(), &params.to_bytes()).expect("prove");
+        let fresh = proof.rerandomize(&vk, &mut OsRng).expect("rerandomize");
+
+        assert_ne!(fresh.to_bytes(), proof.to_bytes());
+        assert!(verify(&vk, &proof.to_bytes(), witness.to_input()).expect("verify"));
+        assert!(verify(&vk, &fresh.to_bytes(), witness.to_input()).expect("verify"));
+
+        // Only valid for the statement the original proved
+        let mut other = witness;
+        other.outputs = vec![Amount::new(Value(4), Nonce(3))];
+        assert!(!verify(&vk, &fresh.to_bytes(), other.to_input()).expect("verify"));
+    }
+
+    #[test]
+    fn outputs_must_reach_min_output() {
+        let params = trust_setup(1, 2);
+        let witness = |min_output| Witness {
+            inputs: vec![Amount::new(Value(10), Nonce(1))],
+            outputs: vec![
+                Amount::new(Value(4), Nonce(2)),
+                Amount::new(Value(6), Nonce(3)),
+            ],
+            min_output,
+            ..witness()
+        };
+
+        let proof = generate_proof(witness(4), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+
+        let vk = params.verifying_key();
+        assert_eq!(witness(4).to_input().min_output(), 4);
+        assert!(verify(&vk, &proof, witness(4).to_input()).expect("verify"));
+        assert!(!verify(&vk, &proof, witness(5).to_input()).expect("verify"));
+
+        match check_witness(&witness(5)) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected output below min output"),
+        }
+        match generate_proof(witness(5), &params.to_bytes()) {
+            Err(ProveError::Synthesis(SynthesisError::Unsatisfiable)) => (),
+            _ => panic!("expected output below min output"),
+        }
+    }
+
+    #[test]
+    fn verify_verbose_reports_public_inputs() {
+        let params = trust_setup(2, 1);
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(3), Nonce(1)),
+                Amount::new(Value(4), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(7), Nonce(3))],
+            ..witness()
+        };
+
+        let proof = generate_proof(witness.clone(), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let vk = params.verifying_key();
+
+        let report = verify_verbose(&vk, &proof, witness.to_input()).expect("verify");
+        assert!(report.verified);
+        // Recursive hash and two nullifiers take two elements each, the fee,
+        // minimum output and both nonce bounds one each
+        assert_eq!(report.public_inputs.len(), 2 + 2 * 2 + 1 + 1 + 2);
+        assert_eq!(
+            report.public_inputs,
+            witness.to_input().public_inputs::<Bls12>()
+        );
+    }
+
+    #[test]
+    fn amounts_above_u128_max() {
+        let wide = U256 {
+            hi: 1,
+            lo: u128::MAX - 1,
+        };
+        assert_eq!(
+            U256::from(u128::MAX).checked_add(U256::from(u128::MAX)),
+            Some(wide)
+        );
+        assert_ne!(
+            Amount::new_u256(wide, Nonce(3)).note_hash(),
+            Amount::new(Value(wide.lo), Nonce(3)).note_hash()
+        );
+
+        let params = trust_setup(2, 1);
+        // The low limbs carry into the high one, and the output only clears
+        // the minimum by borrowing from its high limb
+        let witness = |hi| Witness {
+            inputs: vec![
+                Amount::new(Value(u128::MAX), Nonce(1)),
+                Amount::new(Value(u128::MAX), Nonce(2)),
+            ],
+            outputs: vec![Amount::new_u256(U256 { hi, ..wide }, Nonce(3))],
+            min_output: u128::MAX,
+            ..witness()
+        };
+
+        let proof = generate_proof(witness(1), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let vk = params.verifying_key();
+        assert!(verify(&vk, &proof, witness(1).to_input()).expect("verify"));
+        assert!(!verify(&vk, &proof, witness(2).to_input()).expect("verify"));
+
+        match check_witness(&witness(2)) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected unbalanced high limbs"),
+        }
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn parallel_and_serial_proofs_verify() {
+        let params = trust_setup(1, 1);
+        let witnesses = (1..5)
+            .map(|i| Witness {
+                inputs: vec![Amount::new(Value(i), Nonce(2 * i))],
+                outputs: vec![Amount::new(Value(i), Nonce(2 * i + 1))],
+                ..witness()
+            })
+            .collect::<Vec<_>>();
+
+        let vk = params.verifying_key();
+        let parallel = generate_proofs_parallel(witnesses.clone(), &params.to_bytes());
+        assert_eq!(parallel.len(), witnesses.len());
+
+        for (witness, proof) in witnesses.into_iter().zip(parallel) {
+            let parallel_proof = proof.expect("prove").to_bytes();
+            assert!(verify(&vk, &parallel_proof, witness.to_input()).expect("verify"));
+
+            let serial_proof = generate_proof(witness.clone(), &params.to_bytes())
+                .expect("prove")
+                .to_bytes();
+            assert!(verify(&vk, &serial_proof, witness.to_input()).expect("verify"));
+        }
+
+        let bad_params = generate_proofs_parallel(vec![witness(), witness()], &[0u8; 4]);
+        assert_eq!(bad_params.len(), 2);
+        for proof in bad_params {
+            match proof {
+                Err(ProveError::Params(_)) => (),
+                _ => panic!("expected params error"),
+            }
+        }
+    }
+
+    #[test]
+    fn note_preimage_matches_native_hash() {
+        assert_eq!(NOTE_HASH_BLOCKS, 2);
+
+        let amount = Amount::new_u256(
+            U256 {
+                hi: 3,
+                lo: u128::MAX,
+            },
+            Nonce(9),
+        )
+        .with_blinding(Blinding(4));
+        assert_eq!(amount.to_bytes().len() * 8, NOTE_PREIMAGE_BITS);
+
+        let mut cs = ConstraintCounter::default();
+        let preimage = amount.alloc_preimage::<Bls12, _>(&mut cs).expect("alloc");
+        assert_eq!(preimage.len(), NOTE_PREIMAGE_BITS);
+
+        let hash = hash_preimage::<Bls12, _>(&mut cs, &preimage, HashFunction::Sha256)
+            .expect("hash")
+            .iter()
+            .map(|bit| bit.get_value().expect("value"))
+            .collect::<Vec<_>>();
+        assert_eq!(hash, multipack::bytes_to_bits(&amount.note_hash()));
+    }
+
+    #[test]
+    fn errors_convert_into_zk42_error() {
+        let params = trust_setup(1, 1);
+        let witness = |outputs| Witness {
+            inputs: vec![Amount::new(Value(2), Nonce(1))],
+            outputs,
+            ..witness()
+        };
+
+        let prove = |witness, params: &[u8]| -> Result<Proof, Zk42Error> {
+            Ok(generate_proof(witness, params)?)
+        };
+        match prove(witness(vec![Amount::new(Value(2), Nonce(2))]), &[0u8; 4]) {
+            Err(Zk42Error::Io(_)) => (),
+            _ => panic!("expected io error"),
+        }
+        match prove(
+            witness(vec![Amount::new(Value(3), Nonce(2))]),
+            &params.to_bytes(),
+        ) {
+            Err(err @ Zk42Error::Synthesis(SynthesisError::Unsatisfiable)) => {
+                assert!(err.to_string().starts_with("synthesis failed"))
+            }
+            _ => panic!("expected synthesis error"),
+        }
+        match prove(witness(vec![]), &params.to_bytes()) {
+            Err(err @ Zk42Error::Shape { .. }) => assert_eq!(
+                err.to_string(),
+                "params for 1 inputs and 1 outputs, witness has 1 and 0"
+            ),
+            _ => panic!("expected shape error"),
+        }
+
+        let vk = params.verifying_key();
+        let input = witness(vec![]).to_input();
+        match Zk42Error::from(verify(&vk[..10], &[], input).expect_err("bad key")) {
+            Zk42Error::Io(_) => (),
+            _ => panic!("expected io error"),
+        }
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_clears_witness() {
+        let mut witness = Witness {
+            inputs: vec![Amount::new(Value(6), Nonce(1)).with_blinding(Blinding(3))],
+            outputs: vec![Amount::new_u256(U256 { hi: 1, lo: 5 }, Nonce(2))],
+            fee: 1,
+            min_output: 5,
+            ..witness()
+        };
+
+        witness.zeroize();
+        let zero = Amount::new(Value(0), Nonce(0));
+        assert_eq!(witness.inputs, vec![zero]);
+        assert_eq!(witness.outputs, vec![zero]);
+        assert_eq!((witness.fee, witness.min_output), (0, 0));
+    }
+
+    #[test]
+    fn blake2s_round_trip_with_fewer_constraints() {
+        assert!(constraint_count_with_hash(2, 1, HashFunction::Blake2s) < constraint_count(2, 1));
+
+        let params = trust_setup_with_hash(2, 1, HashFunction::Blake2s);
+        let witness = Witness {
+            inputs: vec![
+                Amount::new(Value(3), Nonce(1)),
+                Amount::new(Value(4), Nonce(2)),
+            ],
+            outputs: vec![Amount::new(Value(7), Nonce(3))],
+            hash: HashFunction::Blake2s,
+            ..witness()
+        };
+        check_witness(&witness).expect("valid witness");
+
+        let proof = generate_proof(witness.clone(), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let vk = params.verifying_key();
+        let input = witness.to_input();
+        assert_eq!(
+            Input::from_hex(&input.to_hex()).expect("hex").hash,
+            input.hash
+        );
+        assert!(verify(&vk, &proof, input).expect("verify"));
+
+        // Nullifiers don't depend on the hash, the recursive hash does
+        let mut sha256 = witness.clone();
+        sha256.hash = HashFunction::Sha256;
+        assert_eq!(
+            sha256.to_input().nullifiers(),
+            witness.to_input().nullifiers()
+        );
+        assert!(!verify(&vk, &proof, sha256.to_input()).expect("verify"));
+    }
+
+    #[test]
+    fn verify_with_inputs_agrees_with_verify() {
+        let params = trust_setup(1, 1);
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(5), Nonce(1))],
+            outputs: vec![Amount::new(Value(5), Nonce(2))],
+            ..witness()
+        };
+
+        let proof = generate_proof(witness.clone(), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let vk = params.verifying_key();
+
+        let input = witness.to_input();
+        let inputs = input.public_inputs::<Bls12>();
+        assert!(verify(&vk, &proof, input).expect("verify"));
+        assert!(verify_with_inputs(&vk, &proof, &inputs).expect("verify"));
+
+        let verifier = PreparedVerifier::from_bytes(&vk).expect("vk");
+        let parsed = Proof::from_bytes(&proof).expect("proof");
+        assert!(verifier.verify_parsed(&parsed, &inputs).expect("verify"));
+        let key = VerifyingKey::<Bls12>::from_bytes(&vk).expect("vk").0;
+        assert!(
+            verify_core::verify(&verify_core::prepare(&key), &parsed.0, &inputs).expect("verify")
+        );
+
+        let mut tampered = inputs.clone();
+        tampered[0].add_assign(&Fr::one());
+        assert!(!verify_with_inputs(&vk, &proof, &tampered).expect("verify"));
+        assert!(verify_with_inputs(&vk, &proof, &inputs[1..]).is_err());
+    }
+
+    #[test]
+    fn aggregate_inputs_is_ordered_and_reproducible() {
+        let input_with_fee = |nonce, fee| {
+            Witness {
+                inputs: vec![Amount::new(Value(5), Nonce(nonce))],
+                outputs: vec![Amount::new(Value(5 - fee), Nonce(nonce + 1))],
+                fee,
+                ..witness()
+            }
+            .to_input()
+        };
+        let input = |nonce| input_with_fee(nonce, 0);
+        let root = aggregate_inputs(&[input(1), input(3)]);
+        assert_eq!(root, aggregate_inputs(&[input(1), input(3)]));
+        assert_ne!(root, aggregate_inputs(&[input(3), input(1)]));
+        assert_ne!(root, aggregate_inputs(&[input(1)]));
+        assert_eq!(aggregate_inputs(&[]), [0u8; 32]);
+
+        // Same recursive hash, only the fee differs
+        let mut charged = input(3);
+        charged.fee = 1;
+        assert_ne!(root, aggregate_inputs(&[input(1), charged]));
+        assert_ne!(root, aggregate_inputs(&[input(1), input_with_fee(3, 1)]));
+
+        let mut preimage = MIXER_AGGREGATE_DOMAIN.to_vec();
+        preimage.extend(input(1).to_bytes());
+        let first = Sha256::digest(&preimage);
+        let mut preimage = MIXER_AGGREGATE_DOMAIN.to_vec();
+        preimage.extend(first.as_slice());
+        preimage.extend(input(3).to_bytes());
+        assert_eq!(root[..], Sha256::digest(&preimage)[..]);
+    }
+
+    #[test]
+    fn proof_components_concatenate_to_bytes() {
+        let params = trust_setup(1, 1);
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(5), Nonce(1))],
+            outputs: vec![Amount::new(Value(5), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+
+        let (a, b, c) = proof.components();
+        assert_eq!((a.len(), b.len(), c.len()), (48, 96, 48));
+        assert_eq!([a, b, c].concat(), proof.to_bytes());
+    }
+
+    #[test]
+    fn overflowing_sums_are_unsatisfiable() {
+        let max = U256 {
+            hi: u128::MAX,
+            lo: u128::MAX - 1,
+        };
+        let witness = Witness {
+            inputs: vec![
+                Amount::new_u256(max, Nonce(1)),
+                Amount::new_u256(max, Nonce(2)),
+            ],
+            outputs: vec![Amount::new_u256(max, Nonce(3))],
+            ..witness()
+        };
+        match check_witness(&witness) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected overflowing inputs to be rejected"),
+        }
+
+        let fees = Witness {
+            inputs: vec![Amount::new_u256(max, Nonce(1))],
+            outputs: vec![Amount::new(Value(0), Nonce(2))],
+            fee: u128::MAX,
+            ..witness.clone()
+        };
+        match check_witness(&fees) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected unbalanced fee to be rejected"),
+        }
+        // Connected steps, so the fee total is the first thing to fail
+        let next = Witness {
+            inputs: fees.outputs.clone(),
+            outputs: vec![Amount::new(Value(0), Nonce(3))],
+            ..fees.clone()
+        };
+        match prove_mixer_chain(&[fees, next], &[]) {
+            Err(ChainError::Overflow) => (),
+            other => panic!("expected fee overflow, got {:?}", other.map(|(_, r)| r)),
+        }
+    }
+
+    #[test]
+    fn output_nonces_within_nonce_range() {
+        let params = trust_setup(1, 1);
+        let nonce_range = NonceRange { min: 10, max: 20 };
+        // Only output nonces are bounded, the input was made earlier
+        let witness = |nonce| Witness {
+            inputs: vec![Amount::new(Value(5), Nonce(1))],
+            outputs: vec![Amount::new(Value(5), Nonce(nonce))],
+            nonce_range,
+            ..witness()
+        };
+
+        let proof = generate_proof(witness(20), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let vk = params.verifying_key();
+        let input = witness(20).to_input();
+        assert_eq!(input.nonce_range(), nonce_range);
+        assert_eq!(
+            Input::from_hex(&input.to_hex()).expect("hex").nonce_range(),
+            nonce_range
+        );
+        assert!(verify(&vk, &proof, input).expect("verify"));
+
+        let mut widened = witness(20);
+        widened.nonce_range = NonceRange { min: 10, max: 21 };
+        assert!(!verify(&vk, &proof, widened.to_input()).expect("verify"));
+
+        check_witness(&witness(10)).expect("nonce at min");
+        for &nonce in &[9, 21] {
+            match check_witness(&witness(nonce)) {
+                Err(SynthesisError::Unsatisfiable) => (),
+                _ => panic!("expected nonce {} outside the range", nonce),
+            }
+        }
+    }
+
+    #[test]
+    fn try_parse_vk_rejects_random_bytes() {
+        let vk = trust_setup(1, 1).verifying_key();
+        assert!(try_parse_vk(&vk).is_ok());
+
+        let mut rng = ChaCha20Rng::from_seed([9u8; 32]);
+        for _ in 0..200 {
+            let mut bytes = vec![0u8; rng.next_u32() as usize % (2 * vk.len())];
+            rng.fill_bytes(&mut bytes);
+            assert!(try_parse_vk(&bytes).is_err());
+        }
+
+        // A valid header claiming more IC points than follow
+        let header = verifying_key_header_bytes::<Bls12>();
+        assert_eq!(header, 3 * 96 + 3 * 192 + 4);
+        let mut forged = vk[..header].to_vec();
+        forged[header - 4..].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert!(try_parse_vk(&forged).is_err());
+
+        // Every point is checked, not just the length
+        let mut flipped = vk.clone();
+        let last = flipped.len() - 1;
+        flipped[last] ^= 1;
+        assert!(try_parse_vk(&flipped).is_err());
+        assert!(try_parse_vk(&vk[..vk.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn proof_points_outside_subgroup_are_rejected() {
+        use pairing::bls12_381::G1Compressed;
+
+        // Most points on G1's curve lie outside the prime order subgroup, so
+        // one turns up among small x coordinates
+        let off_subgroup = (1u8..=255)
+            .filter_map(|x| {
+                let mut repr = G1Compressed::empty();
+                repr.as_mut()[0] = 0x80;
+                repr.as_mut()[47] = x;
+                match (repr.into_affine_unchecked(), repr.into_affine()) {
+                    (Ok(point), Err(_)) => Some(point),
+                    _ => None,
+                }
+            })
+            .next()
+            .expect("point outside the subgroup");
+
+        let params = trust_setup(1, 1);
+        let witness = Witness {
+            inputs: vec![Amount::new(Value(5), Nonce(1))],
+            outputs: vec![Amount::new(Value(5), Nonce(2))],
+            ..witness()
+        };
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+
+        let mut bytes = proof.to_bytes();
+        bytes[..48].copy_from_slice(off_subgroup.into_compressed().as_ref());
+        assert!(Proof::<Bls12>::from_bytes(&bytes).is_err());
+
+        let mut bytes = proof.to_bytes_uncompressed();
+        bytes[..96].copy_from_slice(off_subgroup.into_uncompressed().as_ref());
+        assert!(Proof::<Bls12>::from_bytes_uncompressed(&bytes).is_err());
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn export_r1cs_writes_every_constraint() {
+        let path = std::env::temp_dir().join("mixer-r1cs-1-1.json");
+        export_r1cs(1, 1, &path).expect("export");
+
+        let file = File::open(&path).expect("open");
+        let r1cs: R1csJson = serde_json::from_reader(BufReader::new(file)).expect("parse");
+        assert_eq!(r1cs.constraints.len(), constraint_count(1, 1));
+        // The constant one, then two elements for the recursive hash, two
+        // for the nullifier, and the fee, min output and nonce bounds
+        assert_eq!(r1cs.num_inputs, 1 + 2 + 2 + 4);
+
+        let variables = r1cs.num_inputs + r1cs.num_aux;
+        let one = format!("{:064x}", 1);
+        for constraint in r1cs.constraints.iter() {
+            for (variable, coeff) in constraint
+                .a
+                .iter()
+                .chain(&constraint.b)
+                .chain(&constraint.c)
+            {
+                assert!(*variable < variables);
+                assert_eq!(coeff.len(), one.len());
+            }
+        }
 
-        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input))
+        std::fs::remove_file(&path).expect("remove");
     }
 }
@@ -0,0 +1,60 @@
+// Builds the verification core as a `#![no_std]` crate, so it fails to compile
+// once the core reaches for `std`. A one-constraint circuit stands in for the
+// mixer.
+#![no_std]
+
+extern crate alloc;
+
+#[path = "../src/verify_core.rs"]
+mod verify_core;
+
+use bellman::{groth16, Circuit, ConstraintSystem, SynthesisError};
+use ff::{Field, PrimeField};
+use pairing::bls12_381::{Bls12, Fr};
+use rand::rngs::OsRng;
+
+use verify_core::VerifyCoreError;
+
+// Knows x with x * x = y, y public
+struct Square(Option<Fr>);
+
+impl Circuit<Bls12> for Square {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), SynthesisError> {
+        let x = self.0;
+        let x_var = cs.alloc(|| "x", || x.ok_or(SynthesisError::AssignmentMissing))?;
+        let y_var = cs.alloc_input(
+            || "y",
+            || {
+                let mut y = x.ok_or(SynthesisError::AssignmentMissing)?;
+                y.square();
+                Ok(y)
+            },
+        )?;
+        cs.enforce(
+            || "x * x = y",
+            |lc| lc + x_var,
+            |lc| lc + x_var,
+            |lc| lc + y_var,
+        );
+
+        Ok(())
+    }
+}
+
+#[test]
+fn verifies_without_std() {
+    let params = groth16::generate_random_parameters::<Bls12, _, _>(Square(None), &mut OsRng)
+        .expect("setup");
+    let x = Fr::from_str("3").expect("x");
+    let mut y = x;
+    y.square();
+    let proof = groth16::create_random_proof(Square(Some(x)), &params, &mut OsRng).expect("prove");
+
+    let vk = verify_core::prepare(&params.vk);
+    assert_eq!(verify_core::verify(&vk, &proof, &[y]), Ok(true));
+    assert_eq!(verify_core::verify(&vk, &proof, &[x]), Ok(false));
+    assert_eq!(
+        verify_core::verify(&vk, &proof, &[]),
+        Err(VerifyCoreError::MalformedVerifyingKey)
+    );
+}
@@ -0,0 +1,124 @@
+use halo::{Coeff, ConstraintSystem, Field, LinearCombination, SynthesisError, Variable};
+
+// Standalone constraint system that evaluates every constraint as it is
+// made and remembers the namespace path of the first one that doesn't hold,
+// e.g. "match current root hash". Each allocation gets a gate of its own, so
+// it is only meant for debugging, not for proving.
+pub struct ConstraintDebugger<F: Field> {
+    a: Vec<F>,
+    b: Vec<F>,
+    c: Vec<F>,
+    path: Vec<String>,
+    failure: Option<String>,
+}
+
+impl<F: Field> ConstraintDebugger<F> {
+    pub fn new() -> Self {
+        ConstraintDebugger {
+            a: vec![F::one()],
+            b: vec![F::zero()],
+            c: vec![F::zero()],
+            path: vec![],
+            failure: None,
+        }
+    }
+
+    // Namespace path of the first unsatisfied constraint
+    pub fn failure(&self) -> Option<&str> {
+        self.failure.as_ref().map(String::as_str)
+    }
+
+    pub fn path(&self) -> String {
+        self.path.join(" / ")
+    }
+
+    fn value(&self, variable: Variable) -> F {
+        match variable {
+            Variable::A(i) => self.a[i],
+            Variable::B(i) => self.b[i],
+            Variable::C(i) => self.c[i],
+        }
+    }
+
+    fn gate(&mut self, a: F, b: F, c: F) -> usize {
+        self.a.push(a);
+        self.b.push(b);
+        self.c.push(c);
+        self.a.len() - 1
+    }
+
+    fn check(&mut self, holds: bool) {
+        if !holds && self.failure.is_none() {
+            self.failure = Some(self.path());
+        }
+    }
+}
+
+impl<F: Field> ConstraintSystem<F> for ConstraintDebugger<F> {
+    type Root = Self;
+
+    const ONE: Variable = Variable::A(0);
+
+    fn alloc<V, A, AR>(&mut self, _: A, value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        let index = self.gate(value()?, F::zero(), F::zero());
+        Ok(Variable::A(index))
+    }
+
+    fn alloc_input<V, A, AR>(&mut self, annotation: A, value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.alloc(annotation, value)
+    }
+
+    fn enforce_zero(&mut self, lc: LinearCombination<F>) {
+        let sum = lc
+            .as_ref()
+            .iter()
+            .fold(F::zero(), |acc, (variable, coeff)| {
+                let value = self.value(*variable);
+                let term = match coeff {
+                    Coeff::Zero => F::zero(),
+                    Coeff::One => value,
+                    Coeff::NegativeOne => -value,
+                    Coeff::Full(coeff) => value * *coeff,
+                };
+                acc + term
+            });
+        self.check(sum == F::zero());
+    }
+
+    fn multiply<V>(&mut self, values: V) -> Result<(Variable, Variable, Variable), SynthesisError>
+    where
+        V: FnOnce() -> Result<(F, F, F), SynthesisError>,
+    {
+        let (a, b, c) = values()?;
+        self.check(a * b == c);
+
+        let index = self.gate(a, b, c);
+        Ok((Variable::A(index), Variable::B(index), Variable::C(index)))
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        self.path.push(name_fn().into());
+    }
+
+    fn pop_namespace(&mut self, _: Option<String>) {
+        self.path.pop();
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
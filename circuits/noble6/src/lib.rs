@@ -5,6 +5,34 @@ use halo::{
 };
 use sha2::{Digest, Sha256};
 
+#[cfg(feature = "debug-constraints")]
+mod debug;
+#[cfg(feature = "profiling")]
+mod profile;
+mod range;
+
+#[cfg(feature = "debug-constraints")]
+use debug::ConstraintDebugger;
+#[cfg(feature = "profiling")]
+use profile::Profiler;
+use range::{enforce_less_or_equal, enforce_range};
+
+// Payload layout: height | root_hash | authority_hash | balances | tx count | txs
+const ROOT_HASH_OFFSET: usize = 8 * 8;
+const AUTHORITY_HASH_OFFSET: usize = ROOT_HASH_OFFSET + 8 * 32;
+const BALANCES_OFFSET: usize = AUTHORITY_HASH_OFFSET + 8 * 32;
+const TX_BYTES: usize = 2 + 2 + 2 + 16 + 8 + 1;
+
+// Number of accounts used when a chain doesn't pick its own
+pub const DEFAULT_ACCOUNTS: usize = 8;
+
+// Number of asset types each account holds a balance of, by default just one
+pub const DEFAULT_ASSETS: usize = 1;
+
+// Transactions per block. Unused slots are zero padded so every payload has
+// the same length.
+pub const MAX_TXS: usize = 4;
+
 fn lc_from_bits<F: Field, CS: ConstraintSystem<F>>(bits: &[Boolean]) -> LinearCombination<F> {
     let mut lc = LinearCombination::zero();
     let mut coeff = Coeff::One;
@@ -15,10 +43,29 @@ fn lc_from_bits<F: Field, CS: ConstraintSystem<F>>(bits: &[Boolean]) -> LinearCo
     lc
 }
 
+// Native value of little-endian bits, for values that pick the circuit shape
+fn bits_to_usize(bits: &[Boolean]) -> Result<usize, SynthesisError> {
+    bits.iter()
+        .map(|b| b.get_value())
+        .enumerate()
+        .map(|(i, bit)| bit.map(|b| if b { 1 << i } else { 0 }))
+        .fold(Some(0), |acc, bit| match (acc, bit) {
+            (Some(acc), Some(bit)) => Some(acc + bit),
+            _ => None,
+        })
+        .ok_or_else(|| SynthesisError::Unsatisfiable)
+}
+
+// At most `F::CAPACITY` bits, so the number can't wrap around the modulus and
+// alias a smaller one
 fn bits_to_num<F: Field, CS: ConstraintSystem<F>>(
     mut cs: CS,
     bits: &[Boolean],
 ) -> Result<AllocatedNum<F>, SynthesisError> {
+    if bits.len() > F::CAPACITY as usize {
+        return Err(SynthesisError::Unsatisfiable);
+    }
+
     // Construct the number from its bits
     let value =
         bits.iter()
@@ -41,6 +88,15 @@ fn bits_to_num<F: Field, CS: ConstraintSystem<F>>(
     Ok(num)
 }
 
+// Payload bits are little-endian within each byte, sha256 gadget consumes
+// and produces big-endian bytes.
+fn to_sha256_bits(bits: &[Boolean]) -> Vec<Boolean> {
+    bits.chunks(8)
+        .map(|byte| byte.iter().rev().cloned())
+        .flatten()
+        .collect()
+}
+
 fn enforce_equality<F: Field, CS: ConstraintSystem<F>>(mut cs: CS, a: &[Boolean], b: &[Boolean]) {
     assert_eq!(a.len(), b.len());
 
@@ -55,114 +111,304 @@ fn enforce_equality<F: Field, CS: ConstraintSystem<F>>(mut cs: CS, a: &[Boolean]
     cs.enforce_zero(a_lc - &b_lc);
 }
 
-#[derive(Debug, Clone, Copy)]
-struct Transaction {
-    from: u16,
-    to: u16,
-    amount: u128,
+// Leaves are sha256 of each account's 16-byte little-endian balances, in asset
+// order, hashed pairwise up to the root exactly as
+// `CChainState::merkle_root_hash` does in the circuit.
+pub fn merkle_root<const N: usize, const K: usize>(balances: &[[u128; K]; N]) -> [u8; 32] {
+    let mut root_hash = [0u8; 32];
+    root_hash.copy_from_slice(&balances_merkle_root(balances));
+    root_hash
+}
+
+fn hash_two(left: &Vec<u8>, right: &Vec<u8>) -> Vec<u8> {
+    let mut combined = left.clone();
+    combined.extend(right.clone());
+
+    Sha256::digest(&combined).to_vec()
+}
+
+fn leaf_hashes<const K: usize>(balances: &[[u128; K]]) -> Vec<Vec<u8>> {
+    balances
+        .iter()
+        .map(|account| Sha256::digest(&account_bytes(account)).to_vec())
+        .collect()
+}
+
+// A layer with an odd number of nodes pairs its last node with itself.
+fn balances_merkle_root<const K: usize>(balances: &[[u128; K]]) -> Vec<u8> {
+    let mut root_hash = leaf_hashes(balances);
+    while root_hash.len() > 1 {
+        root_hash = root_hash
+            .chunks(2)
+            .map(|left_right| hash_two(&left_right[0], &left_right[left_right.len() - 1]))
+            .collect::<Vec<_>>();
+    }
+
+    root_hash.pop().expect("root hash")
+}
+
+// Sibling of each node from the leaf of account `index` up to the root, for
+// `merkle_inclusion`. The last node of an odd layer is its own sibling.
+pub fn merkle_path<const N: usize, const K: usize>(
+    balances: &[[u128; K]; N],
+    index: usize,
+) -> Vec<[u8; 32]> {
+    path_from_leaves(leaf_hashes(balances), index)
+}
+
+fn path_from_leaves(leaves: Vec<Vec<u8>>, index: usize) -> Vec<[u8; 32]> {
+    let mut layer = leaves;
+    let mut index = index;
+    let mut path = vec![];
+    while layer.len() > 1 {
+        let sibling = if index % 2 == 0 {
+            layer.get(index + 1).unwrap_or(&layer[index])
+        } else {
+            &layer[index - 1]
+        };
+        let mut node = [0u8; 32];
+        node.copy_from_slice(sibling);
+        path.push(node);
+
+        layer = layer
+            .chunks(2)
+            .map(|left_right| hash_two(&left_right[0], &left_right[left_right.len() - 1]))
+            .collect();
+        index /= 2;
+    }
+
+    path
+}
+
+// Number of hashes from a leaf to the root of a tree over `leaves` accounts
+fn merkle_depth(leaves: usize) -> usize {
+    let mut width = leaves;
+    let mut depth = 0;
+    while width > 1 {
+        width = (width + 1) / 2;
+        depth += 1;
+    }
+    depth
+}
+
+// Merkle tree over account balances that keeps every layer, so changing one
+// account rehashes only the nodes on its path. Same root as `merkle_root`.
+pub struct IncrementalMerkle<const N: usize, const K: usize = DEFAULT_ASSETS> {
+    // Leaf hashes first, the root alone last
+    layers: Vec<Vec<Vec<u8>>>,
+}
+
+impl<const N: usize, const K: usize> IncrementalMerkle<N, K> {
+    pub fn new(balances: &[[u128; K]; N]) -> Self {
+        let mut layers = vec![leaf_hashes(balances)];
+        while layers[layers.len() - 1].len() > 1 {
+            let layer = layers[layers.len() - 1]
+                .chunks(2)
+                .map(|left_right| hash_two(&left_right[0], &left_right[left_right.len() - 1]))
+                .collect();
+            layers.push(layer);
+        }
+
+        IncrementalMerkle { layers }
+    }
+
+    pub fn root(&self) -> [u8; 32] {
+        let mut root_hash = [0u8; 32];
+        root_hash.copy_from_slice(&self.layers[self.layers.len() - 1][0]);
+        root_hash
+    }
+
+    // Panics if `index` isn't an account
+    pub fn update_leaf(&mut self, index: usize, new_balance: [u128; K]) -> [u8; 32] {
+        self.layers[0][index] = Sha256::digest(&account_bytes(&new_balance)).to_vec();
+
+        let mut index = index;
+        for depth in 1..self.layers.len() {
+            let below = &self.layers[depth - 1];
+            let left = index - index % 2;
+            let node = hash_two(&below[left], below.get(left + 1).unwrap_or(&below[left]));
+
+            index /= 2;
+            self.layers[depth][index] = node;
+        }
+
+        self.root()
+    }
+}
+
+// Payload encoding used by `ChainState::to_bits` and decoded by
+// `CChainState::from_bits`: integers are little-endian bytes, and each byte is
+// split into bits least significant first. The bellman circuits use
+// big-endian instead, so bytes shouldn't be passed between the two.
+fn bytes_to_bits(bytes: &[u8]) -> Vec<bool> {
+    bytes
+        .iter()
+        .map(|byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8))
+        .flatten()
+        .collect()
+}
+
+// Inverse of `bytes_to_bits`, ignoring a trailing partial byte
+fn bits_to_bytes(bits: &[bool]) -> Vec<u8> {
+    bits.chunks_exact(8)
+        .map(|bits| {
+            bits.iter()
+                .rev()
+                .fold(0u8, |byte, bit| (byte << 1) | *bit as u8)
+        })
+        .collect()
+}
+
+fn account_bytes(account: &[u128]) -> Vec<u8> {
+    account
+        .iter()
+        .flat_map(|balance| balance.to_le_bytes().to_vec())
+        .collect()
+}
+
+// Sending to this account destroys the amount instead of crediting it
+pub const BURN_ACCOUNT: u16 = u16::MAX;
+
+// A mint credits `to` out of nothing and needs the mint authority. It must
+// name the same account as `from`, while a transfer to oneself changes nothing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TxKind {
+    Transfer,
+    Mint,
+}
+
+impl TxKind {
+    fn to_byte(self) -> u8 {
+        match self {
+            TxKind::Transfer => 0,
+            TxKind::Mint => 1,
+        }
+    }
+
+    fn from_byte(byte: u8) -> Option<Self> {
+        match byte {
+            0 => Some(TxKind::Transfer),
+            1 => Some(TxKind::Mint),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transaction {
+    pub from: u16,
+    pub to: u16,
+    pub asset_id: u16,
+    pub amount: u128,
+    // The transaction can't be included in a block below this height
+    pub unlock_height: u64,
+    pub kind: TxKind,
 }
 
 impl Transaction {
-    fn to_bytes(&self) -> Vec<u8> {
-        let from = self.from.to_le_bytes();
-        let to = self.to.to_le_bytes();
-        let amount = self.amount.to_le_bytes();
+    // from | to | asset_id | amount | unlock_height | kind, each little-endian
+    // as in the payload
+    pub fn encode(&self) -> [u8; TX_BYTES] {
+        let mut bytes = [0u8; TX_BYTES];
+        bytes[0..2].copy_from_slice(&self.from.to_le_bytes());
+        bytes[2..4].copy_from_slice(&self.to.to_le_bytes());
+        bytes[4..6].copy_from_slice(&self.asset_id.to_le_bytes());
+        bytes[6..22].copy_from_slice(&self.amount.to_le_bytes());
+        bytes[22..30].copy_from_slice(&self.unlock_height.to_le_bytes());
+        bytes[30] = self.kind.to_byte();
+        bytes
+    }
+
+    // None unless `bytes` is exactly one encoded transaction
+    pub fn decode(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() != TX_BYTES {
+            return None;
+        }
 
-        from.iter()
-            .chain(to.iter())
-            .chain(amount.iter())
-            .cloned()
-            .collect()
+        let field = |offset: usize| u16::from_le_bytes([bytes[offset], bytes[offset + 1]]);
+        let mut amount = [0u8; 16];
+        amount.copy_from_slice(&bytes[6..22]);
+        let mut unlock_height = [0u8; 8];
+        unlock_height.copy_from_slice(&bytes[22..30]);
+
+        Some(Transaction {
+            from: field(0),
+            to: field(2),
+            asset_id: field(4),
+            amount: u128::from_le_bytes(amount),
+            unlock_height: u64::from_le_bytes(unlock_height),
+            kind: TxKind::from_byte(bytes[30])?,
+        })
     }
 }
 
-#[derive(Debug)]
-struct ChainState {
+#[derive(Debug, Clone)]
+pub struct ChainState<const N: usize = DEFAULT_ACCOUNTS, const K: usize = DEFAULT_ASSETS> {
     height: u64,
     root_hash: Vec<u8>,
-    balances: [u128; 8],
-    tx: Option<Transaction>,
+    authority_hash: Vec<u8>,
+    balances: [[u128; K]; N],
+    txs: Vec<Transaction>,
 }
 
-impl ChainState {
-    fn to_bits(self) -> Vec<bool> {
+impl<const N: usize, const K: usize> ChainState<N, K> {
+    pub fn to_bits(self) -> Vec<bool> {
         let balance_bytes = self
             .balances
             .iter()
-            .map(|b| b.to_le_bytes().to_vec())
-            .flatten()
+            .flat_map(|account| account_bytes(account))
             .collect::<Vec<_>>();
 
         let mut bytes = self.height.to_le_bytes().to_vec();
         bytes.extend(self.root_hash.to_vec());
+        bytes.extend(self.authority_hash.to_vec());
         bytes.extend(balance_bytes);
 
-        if let Some(tx) = self.tx {
-            bytes.extend(tx.to_bytes());
+        bytes.push(self.txs.len() as u8);
+        for i in 0..MAX_TXS {
+            match self.txs.get(i) {
+                Some(tx) => bytes.extend(&tx.encode()),
+                None => bytes.extend(&[0u8; TX_BYTES]),
+            }
         }
 
-        bytes
-            .iter()
-            .map(|byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8))
-            .flatten()
-            .collect()
-    }
-
-    fn hash_two(left: &Vec<u8>, right: &Vec<u8>) -> Vec<u8> {
-        let mut combined = left.clone();
-        combined.extend(right.clone());
-
-        Sha256::digest(&combined).to_vec()
+        bytes_to_bits(&bytes)
     }
 
     fn merkle_root_hash(&self) -> Vec<u8> {
-        let leaf_hashes = self
-            .balances
-            .iter()
-            .map(|balance| Sha256::digest(balance.to_le_bytes().as_ref()).to_vec())
-            .collect::<Vec<_>>();
-
-        let mut root_hash = leaf_hashes;
-        while root_hash.len() > 1 {
-            root_hash = root_hash
-                .chunks(2)
-                .map(|left_right| Self::hash_two(&left_right[0], &left_right[1]))
-                .collect::<Vec<_>>();
-        }
-
-        root_hash.pop().expect("root hash")
+        balances_merkle_root(&self.balances)
     }
 
-    fn genesis() -> Self {
-        ChainState {
+    pub fn genesis(authority_hash: [u8; 32]) -> Self {
+        let mut genesis = ChainState {
             height: 0,
             root_hash: vec![],
-            balances: [0u128; 8],
-            tx: None,
-        }
+            authority_hash: authority_hash.to_vec(),
+            balances: [[0u128; K]; N],
+            txs: vec![],
+        };
+
+        genesis.root_hash = genesis.merkle_root_hash();
+        genesis
     }
 
     fn apply_tx(&self, tx: Transaction) -> Self {
-        let mut balances = self.balances.clone();
-        if tx.to == tx.from {
-            // Mint
-            balances[tx.to as usize] += tx.amount;
-        } else {
-            // Transfer
-            // FIXME: overflow
-            balances[tx.from as usize] -= tx.amount;
-            balances[tx.to as usize] += tx.amount;
-        }
-
-        let mut new_state = ChainState {
-            height: self.height + 1,
-            root_hash: vec![],
-            balances,
-            tx: Some(tx),
-        };
+        ChainStateBuilder::new(self)
+            .tx(tx)
+            .build()
+            .expect("apply tx")
+    }
 
-        new_state.root_hash = new_state.merkle_root_hash();
-        new_state
+    // Next block with no transactions, only valid for circuits built with
+    // `ReachCircuit::allowing_empty_blocks`
+    pub fn empty_block(&self) -> Self {
+        ChainState {
+            height: self.height.checked_add(1).expect("height overflow"),
+            root_hash: self.root_hash.clone(),
+            authority_hash: self.authority_hash.clone(),
+            balances: self.balances,
+            txs: vec![],
+        }
     }
 
     #[cfg(test)]
@@ -180,94 +426,413 @@ impl ChainState {
     }
 }
 
-struct CTransaction<F: Field> {
+#[derive(Debug, PartialEq, Eq)]
+pub enum BuildError {
+    MissingTransaction,
+    InvalidAccount,
+    InvalidAsset,
+    InsufficientBalance,
+    Overflow,
+    HeightOverflow,
+    TooManyTransactions,
+    Locked,
+    InvalidMint,
+}
+
+pub struct ChainStateBuilder<'a, const N: usize, const K: usize> {
+    prev_state: &'a ChainState<N, K>,
+    txs: Vec<Transaction>,
+}
+
+impl<'a, const N: usize, const K: usize> ChainStateBuilder<'a, N, K> {
+    pub fn new(prev_state: &'a ChainState<N, K>) -> Self {
+        ChainStateBuilder {
+            prev_state,
+            txs: vec![],
+        }
+    }
+
+    // Transactions are applied in the order they're added
+    pub fn tx(mut self, tx: Transaction) -> Self {
+        self.txs.push(tx);
+        self
+    }
+
+    pub fn build(self) -> Result<ChainState<N, K>, BuildError> {
+        if self.txs.is_empty() {
+            return Err(BuildError::MissingTransaction);
+        }
+        if self.txs.len() > MAX_TXS {
+            return Err(BuildError::TooManyTransactions);
+        }
+
+        let mut balances = self.prev_state.balances;
+        for tx in self.txs.iter() {
+            let (from, to, asset) = (tx.from as usize, tx.to as usize, tx.asset_id as usize);
+            let burn = tx.to == BURN_ACCOUNT;
+            if from >= N || (to >= N && !burn) {
+                return Err(BuildError::InvalidAccount);
+            }
+            if asset >= K {
+                return Err(BuildError::InvalidAsset);
+            }
+            let mint = tx.kind == TxKind::Mint;
+            if mint && from != to {
+                return Err(BuildError::InvalidMint);
+            }
+
+            if !mint {
+                // Transfer or burn. A transfer to oneself is debited then
+                // credited the same amount.
+                balances[from][asset] = balances[from][asset]
+                    .checked_sub(tx.amount)
+                    .ok_or(BuildError::InsufficientBalance)?;
+            }
+            if !burn {
+                balances[to][asset] = balances[to][asset]
+                    .checked_add(tx.amount)
+                    .ok_or(BuildError::Overflow)?;
+            }
+        }
+
+        let height = self
+            .prev_state
+            .height
+            .checked_add(self.txs.len() as u64)
+            .ok_or(BuildError::HeightOverflow)?;
+        if self.txs.iter().any(|tx| tx.unlock_height > height) {
+            return Err(BuildError::Locked);
+        }
+        let mut new_state = ChainState {
+            height,
+            root_hash: vec![],
+            authority_hash: self.prev_state.authority_hash.clone(),
+            balances,
+            txs: self.txs,
+        };
+
+        new_state.root_hash = new_state.merkle_root_hash();
+        Ok(new_state)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TransitionError {
+    // The new state's transactions can't be applied to the old balances
+    Build(BuildError),
+    Height,
+    RootHash,
+    AuthorityHash,
+    Balances,
+}
+
+// Native version of the rules `ReachCircuit` enforces, to catch a doomed
+// transition before proving. Empty blocks pass when built by `empty_block`,
+// though only circuits allowing them accept one. Mints aren't checked against
+// the authority, whose preimage only the prover has.
+pub fn check_transition<const N: usize, const K: usize>(
+    old: &ChainState<N, K>,
+    new: &ChainState<N, K>,
+) -> Result<(), TransitionError> {
+    if old.root_hash != old.merkle_root_hash() || new.root_hash != new.merkle_root_hash() {
+        return Err(TransitionError::RootHash);
+    }
+    if old.authority_hash != new.authority_hash {
+        return Err(TransitionError::AuthorityHash);
+    }
+
+    let expected = if new.txs.is_empty() {
+        old.empty_block()
+    } else {
+        new.txs
+            .iter()
+            .fold(ChainStateBuilder::new(old), |builder, tx| builder.tx(*tx))
+            .build()
+            .map_err(TransitionError::Build)?
+    };
+    if new.height != expected.height {
+        return Err(TransitionError::Height);
+    }
+    if new.balances != expected.balances {
+        return Err(TransitionError::Balances);
+    }
+
+    Ok(())
+}
+
+// An account or asset index past the last one is the only condition reported
+// as `SynthesisError::Violation`. The bound is also constrained, by showing
+// `count - 1 - index` fits in 16 bits.
+fn bounded_index<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    bits: &[Boolean],
+    count: usize,
+) -> Result<u16, SynthesisError> {
+    let index = bits_to_usize(bits)?;
+    if index >= count {
+        return Err(SynthesisError::Violation);
+    }
+
+    let last = (0..16)
+        .map(|i| Boolean::constant(((count - 1) >> i) & 1 == 1))
+        .collect::<Vec<_>>();
+    let headroom = count - 1 - index;
+    let headroom_bits = (0..16)
+        .map(|i| {
+            AllocatedBit::alloc(cs.namespace(|| format!("headroom bit {}", i)), || {
+                Ok((headroom >> i) & 1 == 1)
+            })
+            .map(Boolean::from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    cs.enforce_zero(
+        lc_from_bits::<F, CS>(bits) + &lc_from_bits::<F, CS>(&headroom_bits)
+            - &lc_from_bits::<F, CS>(&last),
+    );
+
+    Ok(index as u16)
+}
+
+// Transactions aren't signed yet, so whoever proves a block can move any
+// account's funds. Checking a signature over `Transaction::encode` needs
+// curve arithmetic in the circuit, and halo doesn't export an ECC or
+// signature gadget to build that on.
+pub struct CTransaction<F: Field> {
     from: u16,               // 16
     to: u16,                 // 16
+    asset_id: u16,           // 16
     amount: AllocatedNum<F>, // 128
+    amount_bits: Vec<Boolean>,
+    unlock_height: AllocatedNum<F>, // 64
+    mint: bool,                     // 8
 }
 
 impl<F: Field> CTransaction<F> {
-    fn from_bits<CS: ConstraintSystem<F>>(
+    // Decodes one encoded transaction, bounding its indices by `accounts`
+    // and `assets`
+    pub fn from_bits<CS: ConstraintSystem<F>>(
         mut cs: CS,
         bits: &[Boolean],
+        accounts: usize,
+        assets: usize,
     ) -> Result<Self, SynthesisError> {
-        if bits.len() != 8 * (4 + 16) {
+        if bits.len() != 8 * TX_BYTES {
             return Err(SynthesisError::Unsatisfiable);
         }
 
-        let convert_to_num = |bits: &[Boolean]| -> Result<_, _> {
-            let num = bits
-                .iter()
-                .map(|b| b.get_value())
-                .enumerate()
-                .map(|(i, bit)| bit.map(|b| if b { 1 << i } else { 0 }))
-                .fold(Some(0), |acc, bit| match (acc, bit) {
-                    (Some(acc), Some(bit)) => Some(acc + bit),
-                    _ => None,
-                });
-
-            num.ok_or_else(|| SynthesisError::Unsatisfiable)
+        let from = bounded_index(cs.namespace(|| "from < accounts"), &bits[0..16], accounts)?;
+        let to = if bits_to_usize(&bits[16..32])? == BURN_ACCOUNT as usize {
+            let burn_bits = vec![Boolean::constant(true); 16];
+            cs.enforce_zero(
+                lc_from_bits::<F, CS>(&bits[16..32]) - &lc_from_bits::<F, CS>(&burn_bits),
+            );
+            BURN_ACCOUNT
+        } else {
+            bounded_index(cs.namespace(|| "to < accounts"), &bits[16..32], accounts)?
         };
+        let asset_id = bounded_index(cs.namespace(|| "asset < assets"), &bits[32..48], assets)?;
 
-        let from = convert_to_num(&bits[0..16])?;
-        if from >= 8 {
-            return Err(SynthesisError::Violation);
+        let amount_bits = bits[48..176].to_vec();
+        let amount = bits_to_num(cs.namespace(|| "tx amount"), &amount_bits)?;
+        // Redundant while the bits above are booleans, but keeps the amount
+        // below 2^128 even if the payload decoding changes
+        enforce_range(cs.namespace(|| "tx amount range"), &amount, 128)?;
+        let unlock_height = bits_to_num(cs.namespace(|| "unlock height"), &bits[176..240])?;
+
+        // The kind picks the circuit shape like the indices above, so it is
+        // pinned to the constant it decodes to
+        let kind_bits = &bits[240..];
+        let kind = TxKind::from_byte(bits_to_usize(kind_bits)? as u8)
+            .ok_or(SynthesisError::Unsatisfiable)?;
+        let kind_byte = (0..8)
+            .map(|i| Boolean::constant((kind.to_byte() >> i) & 1 == 1))
+            .collect::<Vec<_>>();
+        cs.enforce_zero(lc_from_bits::<F, CS>(kind_bits) - &lc_from_bits::<F, CS>(&kind_byte));
+        let mint = kind == TxKind::Mint;
+        if mint && from != to {
+            return Err(SynthesisError::Unsatisfiable);
         }
 
-        let to = convert_to_num(&bits[16..32])?;
-        if to >= 8 {
-            return Err(SynthesisError::Violation);
+        Ok(CTransaction {
+            from,
+            to,
+            asset_id,
+            amount,
+            amount_bits,
+            unlock_height,
+            mint,
+        })
+    }
+
+    pub fn from(&self) -> u16 {
+        self.from
+    }
+
+    pub fn to(&self) -> u16 {
+        self.to
+    }
+
+    pub fn asset_id(&self) -> u16 {
+        self.asset_id
+    }
+
+    pub fn amount(&self) -> &AllocatedNum<F> {
+        &self.amount
+    }
+
+    pub fn unlock_height(&self) -> &AllocatedNum<F> {
+        &self.unlock_height
+    }
+
+    pub fn kind(&self) -> TxKind {
+        if self.mint {
+            TxKind::Mint
+        } else {
+            TxKind::Transfer
         }
+    }
 
-        let amount = bits_to_num(cs.namespace(|| "tx amount"), &bits[32..8 * (4 + 16)])?;
+    // At least one amount bit must be set
+    fn enforce_nonzero_amount<CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+    ) -> Result<(), SynthesisError> {
+        let mut all_zero = Boolean::constant(true);
+        for (i, bit) in self.amount_bits.iter().enumerate() {
+            all_zero = Boolean::and(
+                cs.namespace(|| format!("amount bit {} is zero", i)),
+                &all_zero,
+                &bit.not(),
+            )?;
+        }
+        cs.enforce_zero(all_zero.lc(CS::ONE, Coeff::One));
 
-        Ok(CTransaction { from, to, amount })
+        Ok(())
     }
 }
 
-struct CChainState<F: Field> {
+/// Chain state decoded from payload bits inside the constraint system.
+///
+/// Balances are exposed, account by account with each account's assets in
+/// order, so other gadgets can add their own constraints:
+///
+/// ```
+/// use halo::{is_satisfied, AllocatedBit, Circuit, ConstraintSystem, Field, Fp, SynthesisError};
+/// use noble6::{CChainState, ChainState};
+///
+/// struct EmptyAccount;
+///
+/// impl<F: Field> Circuit<F> for EmptyAccount {
+///     fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+///         let bits = ChainState::<8>::genesis([0u8; 32])
+///             .to_bits()
+///             .into_iter()
+///             .enumerate()
+///             .map(|(i, b)| {
+///                 AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), || Ok(b))
+///             })
+///             .collect::<Result<Vec<_>, _>>()?;
+///
+///         let state = CChainState::<F, 8>::from_bits(cs.namespace(|| "state"), &bits)?;
+///         cs.enforce_zero(state.balances()[0].lc());
+///
+///         Ok(())
+///     }
+/// }
+///
+/// assert!(is_satisfied::<Fp, _>(&EmptyAccount, &[]).unwrap());
+/// ```
+pub struct CChainState<F: Field, const N: usize = DEFAULT_ACCOUNTS, const K: usize = DEFAULT_ASSETS>
+{
     height: AllocatedNum<F>,        // 8 * 8
     root_hash: Vec<Boolean>,        // 32 * 8
-    balances: Vec<AllocatedNum<F>>, // 8 * 8 * 16
+    authority_hash: Vec<Boolean>,   // 32 * 8
+    balances: Vec<AllocatedNum<F>>, // N * K * 8 * 16
     balances_bits: Vec<Vec<Boolean>>,
-    tx: Option<CTransaction<F>>,
+    tx_count: AllocatedNum<F>, // 8
+    txs: Vec<CTransaction<F>>, // MAX_TXS * 8 * 30
 }
 
-impl<F: Field> CChainState<F> {
-    fn from_bits<CS: ConstraintSystem<F>>(
+impl<F: Field, const N: usize, const K: usize> CChainState<F, N, K> {
+    const TX_COUNT_OFFSET: usize = BALANCES_OFFSET + N * K * 8 * 16;
+    const TXS_OFFSET: usize = Self::TX_COUNT_OFFSET + 8;
+    const PAYLOAD_BITS: usize = Self::TXS_OFFSET + MAX_TXS * 8 * TX_BYTES;
+
+    pub fn from_bits<CS: ConstraintSystem<F>>(
         mut cs: CS,
         bits: &[AllocatedBit],
     ) -> Result<Self, SynthesisError> {
+        // Any other length would shift every field after the mismatch
+        if bits.len() != Self::PAYLOAD_BITS {
+            return Err(SynthesisError::Unsatisfiable);
+        }
+
         let bits = bits.iter().cloned().map(Boolean::from).collect::<Vec<_>>();
 
-        let height = bits_to_num(cs.namespace(|| "height"), &bits[0..8 * 8])?;
-        let root_hash = bits[8 * 8..(8 * 8 + 8 * 32)].to_vec();
-        let balances = bits[(8 * 8 + 8 * 32)..(8 * 8 + 8 * 32 + 8 * 8 * 16)]
+        let height = bits_to_num(cs.namespace(|| "height"), &bits[0..ROOT_HASH_OFFSET])?;
+        let root_hash = bits[ROOT_HASH_OFFSET..AUTHORITY_HASH_OFFSET].to_vec();
+        let authority_hash = bits[AUTHORITY_HASH_OFFSET..BALANCES_OFFSET].to_vec();
+        let balances = bits[BALANCES_OFFSET..Self::TX_COUNT_OFFSET]
             .chunks(8 * 16)
             .map(|balance_bits| bits_to_num(cs.namespace(|| "balance"), &balance_bits))
             .collect::<Result<Vec<_>, _>>()?;
-        let balances_bits = bits[(8 * 8 + 8 * 32)..(8 * 8 + 8 * 32 + 8 * 8 * 16)]
-            .chunks(8 * 16)
+        let balances_bits = bits[BALANCES_OFFSET..Self::TX_COUNT_OFFSET]
+            .chunks(K * 8 * 16)
             .map(|bits| bits.to_vec())
             .collect::<Vec<Vec<Boolean>>>();
 
-        let tx_bits = &bits[(8 * 32 + 8 * 8 * 16)..];
-        let mut tx = None;
-        if !tx_bits.is_empty() {
-            tx = Some(CTransaction::from_bits(cs, tx_bits)?);
+        let count_bits = &bits[Self::TX_COUNT_OFFSET..Self::TXS_OFFSET];
+        let tx_count = bits_to_num(cs.namespace(|| "tx count"), count_bits)?;
+        let count = bits_to_usize(count_bits)?;
+        if count > MAX_TXS {
+            return Err(SynthesisError::Unsatisfiable);
         }
 
+        let txs = bits[Self::TXS_OFFSET..]
+            .chunks(8 * TX_BYTES)
+            .take(count)
+            .enumerate()
+            .map(|(i, tx_bits)| {
+                CTransaction::from_bits(cs.namespace(|| format!("tx {}", i)), tx_bits, N, K)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
         let chain_state = CChainState {
             height,
             root_hash,
+            authority_hash,
             balances,
             balances_bits,
-            tx,
+            tx_count,
+            txs,
         };
 
         Ok(chain_state)
     }
 
+    pub fn balances(&self) -> &[AllocatedNum<F>] {
+        &self.balances
+    }
+
+    pub fn balance(&self, account: usize, asset: usize) -> &AllocatedNum<F> {
+        &self.balances[account * K + asset]
+    }
+
+    pub fn height(&self) -> &AllocatedNum<F> {
+        &self.height
+    }
+
+    // Transactions of the block, up to its transaction count
+    pub fn txs(&self) -> &[CTransaction<F>] {
+        &self.txs
+    }
+
+    // The root hash from the payload, in the bit order `merkle_root_hash`
+    // produces so the two can be compared bit by bit
+    pub fn root_hash(&self) -> Vec<Boolean> {
+        to_sha256_bits(&self.root_hash)
+    }
+
     fn hash_leaf<CS: ConstraintSystem<F>>(
         cs: CS,
         left: &Vec<Boolean>,
@@ -279,14 +844,14 @@ impl<F: Field> CChainState<F> {
         sha256(cs, &combined)
     }
 
-    fn merkle_root_hash<CS: ConstraintSystem<F>>(
-        &self,
+    // Must pair nodes exactly like the native `merkle_root`.
+    fn merkle_root<CS: ConstraintSystem<F>>(
         mut cs: CS,
+        balances_bits: &[Vec<Boolean>],
     ) -> Result<Vec<Boolean>, SynthesisError> {
-        let leaf_hashes = self
-            .balances_bits
+        let leaf_hashes = balances_bits
             .iter()
-            .map(|balance| sha256(cs.namespace(|| "hash(balance)"), &balance))
+            .map(|account| sha256(cs.namespace(|| "hash(account)"), &to_sha256_bits(account)))
             .collect::<Result<Vec<_>, _>>()?;
 
         let mut root_hash = leaf_hashes;
@@ -297,7 +862,7 @@ impl<F: Field> CChainState<F> {
                     Self::hash_leaf(
                         cs.namespace(|| "merkle hash"),
                         &left_right[0],
-                        &left_right[1],
+                        &left_right[left_right.len() - 1],
                     )
                 })
                 .collect::<Result<Vec<_>, _>>()?;
@@ -307,98 +872,2064 @@ impl<F: Field> CChainState<F> {
             .pop()
             .ok_or_else(|| SynthesisError::Unsatisfiable)?)
     }
-}
-
-struct ReachCircuit;
 
-impl<F: Field> RecursiveCircuit<F> for ReachCircuit {
-    fn base_payload(&self) -> Vec<bool> {
-        ChainState::genesis().to_bits()
+    /// Merkle root of all the decoded balances, hashed in the circuit. A
+    /// gadget reusing it can check a payload's root from scratch:
+    ///
+    /// ```
+    /// use halo::{
+    ///     is_satisfied, AllocatedBit, Circuit, Coeff, ConstraintSystem, Field, Fp, SynthesisError,
+    /// };
+    /// use noble6::{CChainState, ChainState, ChainStateBuilder, Transaction, TxKind};
+    ///
+    /// struct RootMatches(ChainState<4>);
+    ///
+    /// impl<F: Field> Circuit<F> for RootMatches {
+    ///     fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+    ///         let bits = self
+    ///             .0
+    ///             .clone()
+    ///             .to_bits()
+    ///             .into_iter()
+    ///             .enumerate()
+    ///             .map(|(i, b)| {
+    ///                 AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), || Ok(b))
+    ///             })
+    ///             .collect::<Result<Vec<_>, _>>()?;
+    ///
+    ///         let state = CChainState::<F, 4>::from_bits(cs.namespace(|| "state"), &bits)?;
+    ///         let root_hash = state.merkle_root_hash(cs.namespace(|| "root hash"))?;
+    ///         for (a, b) in root_hash.iter().zip(state.root_hash()) {
+    ///             cs.enforce_zero(a.lc(CS::ONE, Coeff::One) - &b.lc(CS::ONE, Coeff::One));
+    ///         }
+    ///
+    ///         let tx = &state.txs()[0];
+    ///         assert_eq!((tx.from(), tx.to()), (1, 1));
+    ///         assert_eq!(tx.amount().get_value(), Some(F::from_u64(5)));
+    ///
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let mint = Transaction {
+    ///     from: 1,
+    ///     to: 1,
+    ///     asset_id: 0,
+    ///     amount: 5,
+    ///     unlock_height: 0,
+    ///     kind: TxKind::Mint,
+    /// };
+    /// let genesis = ChainState::<4>::genesis([0u8; 32]);
+    /// let state = ChainStateBuilder::new(&genesis).tx(mint).build().unwrap();
+    /// assert!(is_satisfied::<Fp, _>(&RootMatches(state), &[]).unwrap());
+    /// ```
+    pub fn merkle_root_hash<CS: ConstraintSystem<F>>(
+        &self,
+        cs: CS,
+    ) -> Result<Vec<Boolean>, SynthesisError> {
+        Self::merkle_root(cs, &self.balances_bits)
     }
 
-    fn synthesize<CS: ConstraintSystem<F>>(
-        &self,
-        cs: &mut CS,
-        old_payload: &[AllocatedBit],
-        new_payload: &[AllocatedBit],
-    ) -> Result<(), SynthesisError> {
-        let prev_state = CChainState::from_bits(cs.namespace(|| "previous state"), old_payload)?;
-        let curr_state = CChainState::from_bits(cs.namespace(|| "current status"), new_payload)?;
-        if curr_state.tx.is_none() {
-            return Err(SynthesisError::Unsatisfiable);
+    // Total supply of each asset grows by the amount minted of it in this
+    // block and shrinks by the amount burned. Transfers leave it unchanged.
+    fn enforce_supply_change<CS: ConstraintSystem<F>>(&self, mut cs: CS, prev: &Self) {
+        for asset in 0..K {
+            let supply = |balances: &[AllocatedNum<F>]| {
+                balances
+                    .iter()
+                    .skip(asset)
+                    .step_by(K)
+                    .fold(LinearCombination::zero(), |acc, b| acc + &b.lc())
+            };
+            let minted = self
+                .txs
+                .iter()
+                .filter(|tx| tx.mint && tx.asset_id as usize == asset)
+                .fold(LinearCombination::zero(), |acc, tx| acc + &tx.amount.lc());
+            let burned = self
+                .txs
+                .iter()
+                .filter(|tx| tx.to == BURN_ACCOUNT && tx.asset_id as usize == asset)
+                .fold(LinearCombination::zero(), |acc, tx| acc + &tx.amount.lc());
+            cs.enforce_zero(supply(&self.balances) - &supply(&prev.balances) - &minted + &burned);
         }
+    }
+}
 
-        cs.enforce_zero(curr_state.height.lc() - &prev_state.height.lc() - CS::ONE);
-
-        let prev_root_hash = prev_state.merkle_root_hash(cs.namespace(|| "previous root hash"))?;
-        enforce_equality(
-            cs.namespace(|| "match previous root hash"),
-            &prev_state.root_hash,
-            &prev_root_hash,
-        );
+// Hashes one account up to the root along `path`, as laid out by
+// `merkle_path`, and enforces it matches `root_hash`. That's log(N) hashes
+// instead of N.
+pub fn merkle_inclusion<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    account_bits: &[Boolean],
+    index: usize,
+    path: &[Vec<Boolean>],
+    root_hash: &[Boolean],
+) -> Result<(), SynthesisError> {
+    let node = merkle_path_root(cs.namespace(|| "path root"), account_bits, index, path)?;
+    enforce_equality(
+        cs.namespace(|| "match root hash"),
+        &to_sha256_bits(root_hash),
+        &node,
+    );
 
-        let curr_root_hash = curr_state.merkle_root_hash(cs.namespace(|| "current root hash"))?;
-        enforce_equality(
-            cs.namespace(|| "match current root hash"),
-            &curr_state.root_hash,
-            &curr_root_hash,
-        );
+    Ok(())
+}
 
-        let tx = curr_state.tx.ok_or_else(|| SynthesisError::Unsatisfiable)?;
-        if tx.from == tx.to {
-            // Mint
-            cs.enforce_zero(
-                curr_state.balances[tx.to as usize].lc()
-                    - &prev_state.balances[tx.to as usize].lc()
-                    - &tx.amount.lc(),
-            );
+// Root reached from one account along `path`, in the bit order sha256
+// produces
+fn merkle_path_root<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    account_bits: &[Boolean],
+    index: usize,
+    path: &[Vec<Boolean>],
+) -> Result<Vec<Boolean>, SynthesisError> {
+    let mut node = sha256(
+        cs.namespace(|| "hash(account)"),
+        &to_sha256_bits(account_bits),
+    )?;
+    for (level, sibling) in path.iter().enumerate() {
+        let cs = cs.namespace(|| format!("merkle hash {}", level));
+        node = if (index >> level) & 1 == 0 {
+            CChainState::<F>::hash_leaf(cs, &node, sibling)?
         } else {
-            // Transfer
-            cs.enforce_zero(
-                prev_state.balances[tx.from as usize].lc()
-                    - &curr_state.balances[tx.from as usize].lc()
-                    - &tx.amount.lc(),
-            );
-
-            cs.enforce_zero(
-                curr_state.balances[tx.to as usize].lc()
-                    - &prev_state.balances[tx.to as usize].lc()
-                    - &tx.amount.lc(),
-            );
-        }
-
-        Ok(())
+            CChainState::<F>::hash_leaf(cs, sibling, &node)?
+        };
     }
+
+    Ok(node)
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+// Witnesses the path `merkle_path_root` takes from account `index` of a tree
+// over `leaves`. Without leaf values only the circuit shape is built.
+fn alloc_merkle_path<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    leaves: Option<&Vec<Vec<u8>>>,
+    accounts: usize,
+    index: usize,
+) -> Result<Vec<Vec<Boolean>>, SynthesisError> {
+    let path = leaves.map(|leaves| path_from_leaves(leaves.clone(), index));
+    (0..merkle_depth(accounts))
+        .map(|level| {
+            (0..8 * 32)
+                .map(|i| {
+                    let bit = path
+                        .as_ref()
+                        .map(|path| (path[level][i / 8] >> (7 - i % 8)) & 1u8 == 1u8);
 
-    use std::time::Instant;
+                    AllocatedBit::alloc(
+                        cs.namespace(|| format!("sibling {} bit {}", level, i)),
+                        || bit.ok_or(SynthesisError::AssignmentMissing),
+                    )
+                    .map(Boolean::from)
+                })
+                .collect::<Result<Vec<_>, _>>()
+        })
+        .collect()
+}
 
-    #[test]
-    fn basic_test() {
-        let start = Instant::now();
-        let params0: Params<Ec0> = Params::new(22);
-        let params1: Params<Ec1> = Params::new(22);
-        println!("done, took {:?}", start.elapsed());
+// Leaf hash of an account from its payload bits, None without their values
+fn witness_leaf(account_bits: &[Boolean]) -> Option<Vec<u8>> {
+    let bits = account_bits
+        .iter()
+        .map(|bit| bit.get_value())
+        .collect::<Option<Vec<_>>>()?;
 
-        let genesis = ChainState::genesis();
-        let txs = vec![
+    Some(Sha256::digest(&bits_to_bytes(&bits)).to_vec())
+}
+
+struct MintAuthority<'a> {
+    preimage: Option<&'a [u8; 32]>,
+}
+
+impl<'a> MintAuthority<'a> {
+    fn new(preimage: Option<&'a [u8; 32]>) -> Self {
+        MintAuthority { preimage }
+    }
+
+    fn enforce<F: Field, CS: ConstraintSystem<F>>(
+        &self,
+        mut cs: CS,
+        authority_hash: &[Boolean],
+    ) -> Result<(), SynthesisError> {
+        let preimage_bits = (0..8 * 32)
+            .map(|i| {
+                let bit = self
+                    .preimage
+                    .map(|preimage| (preimage[i / 8] >> (7 - i % 8)) & 1u8 == 1u8);
+
+                AllocatedBit::alloc(cs.namespace(|| format!("preimage bit {}", i)), || {
+                    bit.ok_or(SynthesisError::AssignmentMissing)
+                })
+                .map(Boolean::from)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let preimage_hash = sha256(cs.namespace(|| "sha256(preimage)"), &preimage_bits)?;
+        enforce_equality(
+            cs.namespace(|| "match authority hash"),
+            &to_sha256_bits(authority_hash),
+            &preimage_hash,
+        );
+
+        Ok(())
+    }
+}
+
+// Allocates `balance + amount` or `balance - amount`, which must fit in 128
+// bits
+fn update_balance<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    balance: &AllocatedNum<F>,
+    amount: &AllocatedNum<F>,
+    credit: bool,
+) -> Result<AllocatedNum<F>, SynthesisError> {
+    let updated = AllocatedNum::alloc(cs.namespace(|| "updated balance"), || {
+        match (balance.get_value(), amount.get_value()) {
+            (Some(balance), Some(amount)) if credit => Ok(balance + amount),
+            (Some(balance), Some(amount)) => Ok(balance - amount),
+            _ => Err(SynthesisError::AssignmentMissing),
+        }
+    })?;
+
+    if credit {
+        cs.enforce_zero(updated.lc() - &balance.lc() - &amount.lc());
+    } else {
+        cs.enforce_zero(balance.lc() - &updated.lc() - &amount.lc());
+    }
+
+    enforce_range(cs.namespace(|| "updated balance range"), &updated, 128)?;
+
+    Ok(updated)
+}
+
+struct ReachCircuit<const N: usize = DEFAULT_ACCOUNTS, const K: usize = DEFAULT_ASSETS> {
+    authority_hash: [u8; 32],
+    mint_preimage: Option<[u8; 32]>,
+    reject_zero_amounts: bool,
+    allow_empty_blocks: bool,
+    start: Option<ChainState<N, K>>,
+}
+
+impl<const N: usize, const K: usize> ReachCircuit<N, K> {
+    fn new(authority_hash: [u8; 32]) -> Self {
+        ReachCircuit {
+            authority_hash,
+            mint_preimage: None,
+            reject_zero_amounts: false,
+            allow_empty_blocks: false,
+            start: None,
+        }
+    }
+
+    fn with_mint_preimage(mut self, preimage: [u8; 32]) -> Self {
+        self.mint_preimage = Some(preimage);
+        self
+    }
+
+    // Zero-amount transactions change nothing but still cost a block
+    fn rejecting_zero_amounts(mut self) -> Self {
+        self.reject_zero_amounts = true;
+        self
+    }
+
+    // Blocks without transactions keep every balance and the root, and only
+    // advance the height by one
+    fn allowing_empty_blocks(mut self) -> Self {
+        self.allow_empty_blocks = true;
+        self
+    }
+
+    // Recursion starts from `state` instead of genesis, so a proof attests
+    // to the chain going from its height to the payload's. The state is
+    // taken on trust, like genesis is.
+    fn starting_from(mut self, state: ChainState<N, K>) -> Self {
+        self.start = Some(state);
+        self
+    }
+
+    fn start_payload(&self) -> Vec<bool> {
+        match &self.start {
+            Some(state) => state.clone().to_bits(),
+            None => self.genesis_payload(),
+        }
+    }
+
+    // Height the recursion starts from, 0 unless `starting_from` was used
+    pub fn start_height(&self) -> u64 {
+        self.start.as_ref().map_or(0, |state| state.height)
+    }
+
+    // First and last height a proof over this payload covers. None if the
+    // length is wrong.
+    pub fn payload_heights(&self, payload: &[bool]) -> Option<(u64, u64)> {
+        Some((self.start_height(), self.payload_height(payload)?))
+    }
+
+    // Root of the all-zero balances every chain starts from
+    pub fn genesis_root() -> [u8; 32] {
+        merkle_root(&[[0u128; K]; N])
+    }
+
+    // A verifier checks this before accepting the first proof of a chain
+    pub fn is_genesis(&self, payload: &[bool]) -> bool {
+        payload == self.genesis_payload().as_slice()
+    }
+
+    // Chain height recorded in a payload. Each transaction advances it by one
+    // and so does an empty block, so it isn't the number of transitions: one
+    // block of 4 transactions adds 4. It is absolute, starting from the
+    // state's height under `starting_from`. There is no `proof_height`: halo
+    // keeps the payload a `RecursiveProof` commits to private, so the height
+    // only means something for the exact payload the proof was verified
+    // against. None if the length is wrong.
+    pub fn payload_height(&self, payload: &[bool]) -> Option<u64> {
+        if payload.len() != self.genesis_payload().len() {
+            return None;
+        }
+
+        let height = payload[..64]
+            .iter()
+            .rev()
+            .fold(0u64, |height, bit| (height << 1) | *bit as u64);
+        Some(height)
+    }
+
+    // Amount of each asset minted by the transition a payload ends in. Mints
+    // are ordinary transactions in the public payload, so an auditor can sum
+    // these across a chain. None if the payload is malformed.
+    pub fn payload_minted(&self, payload: &[bool]) -> Option<[u128; K]> {
+        if payload.len() != self.genesis_payload().len() {
+            return None;
+        }
+
+        let bytes = bits_to_bytes(payload);
+        let tx_count_offset = BALANCES_OFFSET / 8 + N * K * 16;
+        let tx_count = bytes[tx_count_offset] as usize;
+        if tx_count > MAX_TXS {
+            return None;
+        }
+
+        let mut minted = [0u128; K];
+        for tx in bytes[tx_count_offset + 1..].chunks(TX_BYTES).take(tx_count) {
+            let tx = Transaction::decode(tx)?;
+            if tx.kind != TxKind::Mint {
+                continue;
+            }
+
+            let total = minted.get_mut(tx.asset_id as usize)?;
+            *total = total.checked_add(tx.amount)?;
+        }
+
+        Some(minted)
+    }
+
+    // Checks a transition outside of any proof. On failure the message names
+    // the namespace of the first broken constraint, e.g.
+    // "match current root hash".
+    #[cfg(feature = "debug-constraints")]
+    pub fn debug_synthesize<F: Field>(
+        &self,
+        old_payload: &[bool],
+        new_payload: &[bool],
+    ) -> Result<(), String> {
+        let mut cs = ConstraintDebugger::<F>::new();
+        let mut alloc = |name: &str, payload: &[bool]| {
+            payload
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    AllocatedBit::alloc(cs.namespace(|| format!("{} bit {}", name, i)), || Ok(b))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+        let old_payload = alloc("old payload", old_payload);
+        let new_payload = alloc("new payload", new_payload);
+
+        old_payload
+            .and_then(|old| self.enforce_transition(&mut cs, &old, &new_payload?))
+            .map_err(|err| format!("synthesis failed: {:?}", err))?;
+
+        match cs.failure() {
+            Some(path) => Err(format!("unsatisfied constraint in {}", path)),
+            None => Ok(()),
+        }
+    }
+
+    fn genesis_payload(&self) -> Vec<bool> {
+        ChainState::<N, K>::genesis(self.authority_hash).to_bits()
+    }
+
+    fn enforce_transition<F: Field, CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        old_payload: &[AllocatedBit],
+        new_payload: &[AllocatedBit],
+    ) -> Result<(), SynthesisError> {
+        let prev_state =
+            CChainState::<F, N, K>::from_bits(cs.namespace(|| "previous state"), old_payload)?;
+        let curr_state =
+            CChainState::<F, N, K>::from_bits(cs.namespace(|| "current status"), new_payload)?;
+        if curr_state.txs.is_empty() {
+            if !self.allow_empty_blocks {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+
+            cs.enforce_zero(curr_state.height.lc() - &prev_state.height.lc() - CS::ONE);
+            enforce_equality(
+                cs.namespace(|| "keep root hash"),
+                &prev_state.root_hash,
+                &curr_state.root_hash,
+            );
+        } else {
+            cs.enforce_zero(
+                curr_state.height.lc() - &prev_state.height.lc() - &curr_state.tx_count.lc(),
+            );
+        }
+        // Redundant while the height is decoded from 64 bits, but keeps the
+        // increment above from wrapping past u64::MAX if that changes
+        enforce_range(
+            cs.namespace(|| "current height range"),
+            &curr_state.height,
+            64,
+        )?;
+
+        enforce_equality(
+            cs.namespace(|| "keep authority hash"),
+            &prev_state.authority_hash,
+            &curr_state.authority_hash,
+        );
+
+        // Only the accounts the block touches are hashed. Each is shown in
+        // the root so far along a witnessed path, then swapped for its new
+        // balances along the same path. The rest keep their balances by the
+        // checks below, so the last root covers the current payload as long
+        // as the previous payload matched its root, which holds from genesis.
+        let mut touched = curr_state
+            .txs
+            .iter()
+            .flat_map(|tx| vec![tx.from, tx.to])
+            .filter(|&account| account != BURN_ACCOUNT)
+            .map(usize::from)
+            .collect::<Vec<_>>();
+        touched.sort_unstable();
+        touched.dedup();
+
+        let mut leaves = prev_state
+            .balances_bits
+            .iter()
+            .map(|account_bits| witness_leaf(account_bits))
+            .collect::<Option<Vec<_>>>();
+        let mut root_hash = prev_state.root_hash.clone();
+        {
+            let mut cs = cs.namespace(|| "current root hash");
+            for account in touched {
+                let mut cs = cs.namespace(|| format!("account {}", account));
+                let path = alloc_merkle_path(cs.namespace(|| "path"), leaves.as_ref(), N, account)?;
+                merkle_inclusion(
+                    cs.namespace(|| "previous balances"),
+                    &prev_state.balances_bits[account],
+                    account,
+                    &path,
+                    &root_hash,
+                )?;
+
+                let curr_bits = &curr_state.balances_bits[account];
+                root_hash = to_sha256_bits(&merkle_path_root(
+                    cs.namespace(|| "current balances"),
+                    curr_bits,
+                    account,
+                    &path,
+                )?);
+                leaves = leaves.and_then(|mut leaves| {
+                    leaves[account] = witness_leaf(curr_bits)?;
+                    Some(leaves)
+                });
+            }
+        }
+        enforce_equality(
+            cs.namespace(|| "match current root hash"),
+            &curr_state.root_hash,
+            &root_hash,
+        );
+
+        // Apply the transactions in order, range checking every intermediate
+        // balance so none of them can wrap around the field
+        let mut balances = prev_state.balances.clone();
+        for (i, tx) in curr_state.txs.iter().enumerate() {
+            let mut cs = cs.namespace(|| format!("apply tx {}", i));
+            let asset = tx.asset_id as usize;
+            let from = tx.from as usize * K + asset;
+            if self.reject_zero_amounts {
+                tx.enforce_nonzero_amount(cs.namespace(|| "nonzero amount"))?;
+            }
+            // Both heights are decoded from 64 bits
+            enforce_less_or_equal(
+                cs.namespace(|| "unlock height <= height"),
+                &tx.unlock_height,
+                &curr_state.height,
+                64,
+            )?;
+
+            if !tx.mint {
+                // Transfer or burn, which can drain the sender but not more
+                enforce_less_or_equal(
+                    cs.namespace(|| "amount <= sender balance"),
+                    &tx.amount,
+                    &balances[from],
+                    128,
+                )?;
+                balances[from] = update_balance(
+                    cs.namespace(|| "sender balance"),
+                    &balances[from],
+                    &tx.amount,
+                    false,
+                )?;
+            }
+            if tx.to != BURN_ACCOUNT {
+                let to = tx.to as usize * K + asset;
+                balances[to] = update_balance(
+                    cs.namespace(|| "receiver balance"),
+                    &balances[to],
+                    &tx.amount,
+                    true,
+                )?;
+            }
+        }
+
+        if curr_state.txs.iter().any(|tx| tx.mint) {
+            MintAuthority::new(self.mint_preimage.as_ref()).enforce(
+                cs.namespace(|| "mint authority"),
+                &curr_state.authority_hash,
+            )?;
+        }
+
+        let mut cs = cs.namespace(|| "balances");
+        for (applied, curr) in balances.iter().zip(curr_state.balances.iter()) {
+            cs.enforce_zero(curr.lc() - &applied.lc());
+        }
+
+        // Implied by the checks above, but it ties the previous and current
+        // balance totals together independently of how the per-account
+        // constraints are written.
+        curr_state.enforce_supply_change(cs.namespace(|| "total supply"), &prev_state);
+
+        Ok(())
+    }
+}
+
+impl<F: Field, const N: usize, const K: usize> RecursiveCircuit<F> for ReachCircuit<N, K> {
+    fn base_payload(&self) -> Vec<bool> {
+        self.start_payload()
+    }
+
+    fn synthesize<CS: ConstraintSystem<F>>(
+        &self,
+        cs: &mut CS,
+        old_payload: &[AllocatedBit],
+        new_payload: &[AllocatedBit],
+    ) -> Result<(), SynthesisError> {
+        // Logs constraint counts per phase, e.g. "current root hash: N constraints"
+        #[cfg(feature = "profiling")]
+        let cs = &mut Profiler::new(cs);
+
+        self.enforce_transition(cs, old_payload, new_payload)?;
+
+        #[cfg(feature = "profiling")]
+        cs.log_phases();
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use halo::{is_satisfied, Circuit, Fp};
+    use std::time::Instant;
+
+    struct Transition<const N: usize, const K: usize> {
+        circuit: ReachCircuit<N, K>,
+        prev_state: ChainState<N, K>,
+        curr_state: ChainState<N, K>,
+    }
+
+    impl<F: Field, const N: usize, const K: usize> Circuit<F> for Transition<N, K> {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let old_payload = self
+                .prev_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "old payload"))?;
+            let new_payload = self
+                .curr_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "new payload"))?;
+
+            RecursiveCircuit::<F>::synthesize(&self.circuit, cs, &old_payload, &new_payload)
+        }
+    }
+
+    fn is_valid_transition<const N: usize, const K: usize>(
+        circuit: ReachCircuit<N, K>,
+        prev_state: &ChainState<N, K>,
+        curr_state: &ChainState<N, K>,
+    ) -> bool {
+        let transition = Transition {
+            circuit,
+            prev_state: prev_state.clone(),
+            curr_state: curr_state.clone(),
+        };
+
+        is_satisfied::<Fp, _>(&transition, &[]).unwrap_or(false)
+    }
+
+    fn single_asset<const N: usize>(balances: [u128; N]) -> [[u128; 1]; N] {
+        let mut accounts = [[0u128; 1]; N];
+        for (account, balance) in accounts.iter_mut().zip(balances.iter()) {
+            account[0] = *balance;
+        }
+        accounts
+    }
+
+    fn state_with<const N: usize>(
+        height: u64,
+        balances: [u128; N],
+        txs: &[Transaction],
+    ) -> ChainState<N> {
+        state_with_assets(height, single_asset(balances), txs)
+    }
+
+    fn state_with_assets<const N: usize, const K: usize>(
+        height: u64,
+        balances: [[u128; K]; N],
+        txs: &[Transaction],
+    ) -> ChainState<N, K> {
+        let mut state = ChainState {
+            height,
+            root_hash: vec![],
+            authority_hash: vec![0u8; 32],
+            balances,
+            txs: txs.to_vec(),
+        };
+
+        state.root_hash = state.merkle_root_hash();
+        state
+    }
+
+    #[test]
+    fn basic_test() {
+        let start = Instant::now();
+        let params0: Params<Ec0> = Params::new(22);
+        let params1: Params<Ec1> = Params::new(22);
+        println!("done, took {:?}", start.elapsed());
+
+        let genesis = ChainState::<8>::genesis([0u8; 32]);
+        let txs = vec![
             Transaction {
                 from: 0,
                 to: 0,
+                asset_id: 0,
                 amount: 500,
+                unlock_height: 0,
+                kind: TxKind::Mint,
             },
             Transaction {
                 from: 0,
                 to: 1,
+                asset_id: 0,
                 amount: 100,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
             },
         ];
 
-        let circuit = ReachCircuit;
+        let circuit = ReachCircuit::<8>::new([0u8; 32]);
         // RecursiveProof::<Ec1, Ec0>::create_proof(&params1, &params0, None, &circuit, &);
     }
+
+    #[test]
+    fn mint_requires_authority_preimage() {
+        let preimage = [7u8; 32];
+        let mut authority_hash = [0u8; 32];
+        authority_hash.copy_from_slice(&Sha256::digest(&preimage));
+
+        let genesis = ChainState::<8>::genesis(authority_hash);
+        let minted = genesis.apply_tx(Transaction {
+            from: 0,
+            to: 0,
+            asset_id: 0,
+            amount: 500,
+            unlock_height: 0,
+            kind: TxKind::Mint,
+        });
+
+        let circuit = ReachCircuit::new(authority_hash).with_mint_preimage(preimage);
+        assert!(is_valid_transition(circuit, &genesis, &minted));
+
+        let circuit = ReachCircuit::new(authority_hash).with_mint_preimage([8u8; 32]);
+        assert!(!is_valid_transition(circuit, &genesis, &minted));
+    }
+
+    struct MerkleRoot {
+        balances: Vec<[u128; 1]>,
+    }
+
+    impl<F: Field> Circuit<F> for MerkleRoot {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let balances_bits = self
+                .balances
+                .iter()
+                .enumerate()
+                .map(|(i, balance)| {
+                    (0..8 * 16)
+                        .map(|j| {
+                            AllocatedBit::alloc(
+                                cs.namespace(|| format!("balance {} bit {}", i, j)),
+                                || Ok((balance[0] >> j) & 1 == 1),
+                            )
+                            .map(Boolean::from)
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let root_hash = balances_merkle_root(&self.balances)
+                .iter()
+                .map(|byte| (0..8).map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8)))
+                .flatten()
+                .collect::<Vec<_>>();
+
+            let computed =
+                CChainState::<F>::merkle_root(cs.namespace(|| "root hash"), &balances_bits)?;
+            enforce_equality(
+                cs.namespace(|| "match root hash"),
+                &to_sha256_bits(&root_hash),
+                &computed,
+            );
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn merkle_root_with_odd_balances() {
+        let hash = |bytes: &[u8]| Sha256::digest(bytes).to_vec();
+        let pair = |left: &[u8], right: &[u8]| hash(&[left, right].concat());
+        let l = (0..7u128)
+            .map(|i| hash(&(i * 100).to_le_bytes()))
+            .collect::<Vec<_>>();
+
+        // The last node of an odd layer is paired with itself
+        let five = {
+            let (a, b, c) = (pair(&l[0], &l[1]), pair(&l[2], &l[3]), pair(&l[4], &l[4]));
+            pair(&pair(&a, &b), &pair(&c, &c))
+        };
+        let seven = {
+            let (a, b) = (pair(&l[0], &l[1]), pair(&l[2], &l[3]));
+            let (c, d) = (pair(&l[4], &l[5]), pair(&l[6], &l[6]));
+            pair(&pair(&a, &b), &pair(&c, &d))
+        };
+
+        for &(count, ref root) in &[(5usize, five), (7, seven)] {
+            let balances = (0..count as u128).map(|i| [i * 100]).collect::<Vec<_>>();
+            assert_eq!(&balances_merkle_root(&balances), root);
+
+            let circuit = MerkleRoot { balances };
+            assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+        }
+    }
+
+    #[test]
+    fn transfer_cannot_overdraw_sender() {
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 150,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let mut balances = prev_state.balances;
+        balances[0][0] = balances[0][0].wrapping_sub(tx.amount);
+        balances[1][0] += tx.amount;
+        let curr_state = state_with_assets(2, balances, &[tx]);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let tx = Transaction { amount: 100, ..tx };
+        let curr_state = prev_state.apply_tx(tx);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+    }
+
+    #[test]
+    fn transfer_amount_bounded_by_sender_balance() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 101,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let mut balances = prev_state.balances;
+        balances[0][0] = balances[0][0].wrapping_sub(tx.amount);
+        balances[1][0] += tx.amount;
+        let curr_state = state_with_assets(2, balances, &[tx]);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let drain = Transaction { amount: 100, ..tx };
+        let curr_state = prev_state.apply_tx(drain);
+        assert_eq!(curr_state.balances[..2], [[0], [100]]);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+    }
+
+    #[test]
+    fn builder_produces_valid_transition() {
+        let prev_state = state_with(3, [500, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 200,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let curr_state = ChainStateBuilder::new(&prev_state)
+            .tx(tx)
+            .build()
+            .expect("build");
+        assert_eq!(curr_state.height, 4);
+        assert_eq!(curr_state.balances[..2], [[300], [200]]);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let overdraw = Transaction { amount: 501, ..tx };
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state)
+                .tx(overdraw)
+                .build()
+                .unwrap_err(),
+            BuildError::InsufficientBalance
+        );
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state).build().unwrap_err(),
+            BuildError::MissingTransaction
+        );
+    }
+
+    #[test]
+    fn merkle_root_matches_manual_hashing() {
+        let balances = [1u128, 2, 3, 4, 5, 6, 7, u128::max_value()];
+
+        let hash = |bytes: &[u8]| Sha256::digest(bytes).to_vec();
+        let pair = |left: &[u8], right: &[u8]| hash(&[left, right].concat());
+
+        let leaves = balances
+            .iter()
+            .map(|b| hash(&b.to_le_bytes()))
+            .collect::<Vec<_>>();
+        let level1 = (0..4)
+            .map(|i| pair(&leaves[2 * i], &leaves[2 * i + 1]))
+            .collect::<Vec<_>>();
+        let level2 = (0..2)
+            .map(|i| pair(&level1[2 * i], &level1[2 * i + 1]))
+            .collect::<Vec<_>>();
+        let root = pair(&level2[0], &level2[1]);
+
+        assert_eq!(merkle_root(&single_asset(balances)).to_vec(), root);
+
+        let state = state_with(0, balances, &[]);
+        assert_eq!(state.root_hash, root);
+    }
+
+    #[test]
+    fn transfer_between_sixteen_accounts() {
+        let mut balances = [0u128; 16];
+        balances[12] = 100;
+
+        let prev_state = state_with(1, balances, &[]);
+        let curr_state = prev_state.apply_tx(Transaction {
+            from: 12,
+            to: 3,
+            asset_id: 0,
+            amount: 40,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        });
+        assert_eq!(
+            (curr_state.balances[12], curr_state.balances[3]),
+            ([60], [40])
+        );
+
+        let circuit = ReachCircuit::<16>::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+    }
+
+    struct TxAmount {
+        tx: Transaction,
+    }
+
+    impl<F: Field> Circuit<F> for TxAmount {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = bytes_to_bits(&self.tx.encode())
+                .into_iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    AllocatedBit::alloc(cs.namespace(|| format!("tx bit {}", i)), || Ok(b))
+                        .map(Boolean::from)
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let tx = CTransaction::from_bits(
+                cs.namespace(|| "tx"),
+                &bits,
+                DEFAULT_ACCOUNTS,
+                DEFAULT_ASSETS,
+            )?;
+
+            let expected = (0..128)
+                .map(|i| (self.tx.amount >> (127 - i)) & 1 == 1)
+                .fold(F::zero(), |acc, bit| {
+                    acc + acc + if bit { F::one() } else { F::zero() }
+                });
+            if tx.amount.get_value() != Some(expected) {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn tx_amount_range_holds_at_u128_max() {
+        for &amount in &[0, 1, u128::max_value()] {
+            let circuit = TxAmount {
+                tx: Transaction {
+                    from: 0,
+                    to: 1,
+                    asset_id: 0,
+                    amount,
+                    unlock_height: 0,
+                    kind: TxKind::Transfer,
+                },
+            };
+            assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+        }
+    }
+
+    #[test]
+    fn transfer_cannot_touch_other_accounts() {
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 30,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let honest = prev_state.apply_tx(tx);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &honest));
+
+        let mut balances = honest.balances;
+        balances[5][0] += 1_000;
+        let inflated = state_with_assets(2, balances, &[tx]);
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &inflated));
+    }
+
+    #[test]
+    fn transition_must_be_consistent_with_tx() {
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 30,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+
+        // Roots are valid for the claimed balances, but the balances don't
+        // follow from applying the transaction
+        for balances in &[
+            [70, 40, 0, 0, 0, 0, 0, 0],
+            [100, 0, 0, 0, 0, 0, 0, 0],
+            [70, 0, 30, 0, 0, 0, 0, 0],
+        ] {
+            let curr_state = state_with(2, *balances, &[tx]);
+            let circuit = ReachCircuit::new([0u8; 32]);
+            assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+        }
+
+        let curr_state = state_with(2, [70, 30, 0, 0, 0, 0, 0, 0], &[tx]);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+    }
+
+    #[test]
+    fn several_transfers_in_one_block() {
+        let prev_state = state_with(1, [100, 50, 0, 0, 0, 0, 0, 0], &[]);
+        let txs = [
+            Transaction {
+                from: 0,
+                to: 1,
+                asset_id: 0,
+                amount: 30,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            },
+            Transaction {
+                from: 1,
+                to: 2,
+                asset_id: 0,
+                amount: 60,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            },
+            Transaction {
+                from: 2,
+                to: 3,
+                asset_id: 0,
+                amount: 10,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            },
+        ];
+
+        let curr_state = txs
+            .iter()
+            .fold(ChainStateBuilder::new(&prev_state), |builder, tx| {
+                builder.tx(*tx)
+            })
+            .build()
+            .expect("build");
+        let balances = [70, 20, 50, 10, 0, 0, 0, 0];
+        assert_eq!(curr_state.height, 4);
+        assert_eq!(curr_state.balances, single_asset(balances));
+        assert_eq!(
+            curr_state.root_hash,
+            merkle_root(&single_asset(balances)).to_vec()
+        );
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        // Same final balances, but account 1 can't pay 60 before receiving 30
+        let reordered = [txs[1], txs[0], txs[2]];
+        let curr_state = state_with(4, balances, &reordered);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+
+        // Height must advance by the number of transactions
+        let curr_state = state_with(2, balances, &txs);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let too_many = (0..=MAX_TXS).fold(ChainStateBuilder::new(&prev_state), |builder, _| {
+            builder.tx(txs[0])
+        });
+        assert_eq!(
+            too_many.build().unwrap_err(),
+            BuildError::TooManyTransactions
+        );
+    }
+
+    #[test]
+    fn zero_amount_rejected_when_enabled() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let transfer = |amount| {
+            let tx = Transaction {
+                from: 0,
+                to: 1,
+                asset_id: 0,
+                amount,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            };
+            let curr_state = prev_state.apply_tx(tx);
+            let circuit = ReachCircuit::new([0u8; 32]).rejecting_zero_amounts();
+            is_valid_transition(circuit, &prev_state, &curr_state)
+        };
+
+        assert!(!transfer(0));
+        assert!(transfer(1));
+
+        // Still allowed by default
+        let curr_state = prev_state.apply_tx(Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 0,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        });
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+    }
+
+    #[cfg(feature = "debug-constraints")]
+    #[test]
+    fn debug_synthesize_names_failing_constraint() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let curr_state = prev_state.apply_tx(Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 30,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        });
+        let circuit = ReachCircuit::<DEFAULT_ACCOUNTS>::new([0u8; 32]);
+        assert_eq!(
+            circuit.debug_synthesize::<Fp>(
+                &prev_state.clone().to_bits(),
+                &curr_state.clone().to_bits()
+            ),
+            Ok(())
+        );
+
+        let mut stale_root = curr_state;
+        stale_root.root_hash = prev_state.root_hash.clone();
+        let err = circuit
+            .debug_synthesize::<Fp>(&prev_state.to_bits(), &stale_root.to_bits())
+            .unwrap_err();
+        assert!(err.contains("match current root hash"), "{}", err);
+    }
+
+    #[cfg(feature = "profiling")]
+    struct ProfiledTransition {
+        prev_state: ChainState,
+        curr_state: ChainState,
+    }
+
+    #[cfg(feature = "profiling")]
+    impl<F: Field> Circuit<F> for ProfiledTransition {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let old_payload = self
+                .prev_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "old payload"))?;
+            let new_payload = self
+                .curr_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "new payload"))?;
+
+            let mut profiler = Profiler::new(cs);
+            ReachCircuit::<DEFAULT_ACCOUNTS>::new([0u8; 32]).enforce_transition(
+                &mut profiler,
+                &old_payload,
+                &new_payload,
+            )?;
+
+            let phases = profiler.phases();
+            for phase in &["previous state", "current root hash", "balances"] {
+                let count = phases
+                    .iter()
+                    .find(|(name, _)| name == phase)
+                    .map(|(_, count)| *count);
+                assert!(count.unwrap_or(0) > 0, "{}", phase);
+            }
+            assert_eq!(
+                phases.iter().map(|(_, count)| count).sum::<usize>() + profiler.unscoped(),
+                profiler.total()
+            );
+            assert!(profiler.unscoped() > 0);
+
+            Ok(())
+        }
+    }
+
+    #[cfg(feature = "profiling")]
+    #[test]
+    fn profiler_counts_constraints_per_phase() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let curr_state = prev_state.apply_tx(Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 30,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        });
+
+        let circuit = ProfiledTransition {
+            prev_state,
+            curr_state,
+        };
+        assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+    }
+
+    #[test]
+    fn empty_block_only_advances_height() {
+        let prev_state = state_with(3, [100, 50, 0, 0, 0, 0, 0, 0], &[]);
+        let curr_state = prev_state.empty_block();
+        assert_eq!(curr_state.height, 4);
+
+        let circuit = ReachCircuit::new([0u8; 32]).allowing_empty_blocks();
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &curr_state));
+
+        let changed = state_with(4, [90, 60, 0, 0, 0, 0, 0, 0], &[]);
+        let circuit = ReachCircuit::new([0u8; 32]).allowing_empty_blocks();
+        assert!(!is_valid_transition(circuit, &prev_state, &changed));
+
+        let skipped = state_with(5, [100, 50, 0, 0, 0, 0, 0, 0], &[]);
+        let circuit = ReachCircuit::new([0u8; 32]).allowing_empty_blocks();
+        assert!(!is_valid_transition(circuit, &prev_state, &skipped));
+    }
+
+    #[test]
+    fn tx_account_index_out_of_range() {
+        for &(from, to) in &[(8, 0), (0, 255)] {
+            let circuit = TxAmount {
+                tx: Transaction {
+                    from,
+                    to,
+                    asset_id: 0,
+                    amount: 1,
+                    unlock_height: 0,
+                    kind: TxKind::Transfer,
+                },
+            };
+            match is_satisfied::<Fp, _>(&circuit, &[]) {
+                Err(SynthesisError::Violation) => (),
+                _ => panic!("expected account index violation for {} -> {}", from, to),
+            }
+        }
+
+        let circuit = TxAmount {
+            tx: Transaction {
+                from: 7,
+                to: 0,
+                asset_id: 0,
+                amount: 1,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            },
+        };
+        assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+    }
+
+    struct BitsToNum {
+        len: usize,
+    }
+
+    impl<F: Field> Circuit<F> for BitsToNum {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = vec![Boolean::constant(true); self.len];
+            bits_to_num(cs.namespace(|| "num"), &bits).map(|_| ())
+        }
+    }
+
+    #[test]
+    fn bits_to_num_rejects_more_bits_than_capacity() {
+        let capacity = <Fp as Field>::CAPACITY as usize;
+
+        let circuit = BitsToNum { len: capacity };
+        assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+
+        let circuit = BitsToNum { len: capacity + 1 };
+        match is_satisfied::<Fp, _>(&circuit, &[]) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected over-long bits to be rejected"),
+        }
+    }
+
+    #[test]
+    fn transfer_moves_only_its_asset() {
+        let mut balances = [[0u128; 3]; 8];
+        balances[0] = [50, 0, 100];
+        balances[1] = [20, 0, 0];
+        let prev_state = state_with_assets(1, balances, &[]);
+
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 2,
+            amount: 40,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+        let curr_state = prev_state.apply_tx(tx);
+        assert_eq!(curr_state.balances[0], [50, 0, 60]);
+        assert_eq!(curr_state.balances[1], [20, 0, 40]);
+
+        let circuit = ReachCircuit::<8, 3>::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        // Same amount moved, but out of asset 0
+        let mut wrong_asset = balances;
+        wrong_asset[0][0] -= 40;
+        wrong_asset[1][0] += 40;
+        let wrong_asset = state_with_assets(2, wrong_asset, &[tx]);
+        let circuit = ReachCircuit::<8, 3>::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &wrong_asset));
+
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state)
+                .tx(Transaction { asset_id: 3, ..tx })
+                .build()
+                .unwrap_err(),
+            BuildError::InvalidAsset
+        );
+    }
+
+    struct GenesisRoot {
+        circuit: ReachCircuit,
+    }
+
+    impl<F: Field> Circuit<F> for GenesisRoot {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let payload = RecursiveCircuit::<F>::base_payload(&self.circuit)
+                .into_iter()
+                .enumerate()
+                .map(|(i, b)| {
+                    AllocatedBit::alloc(cs.namespace(|| format!("payload bit {}", i)), || Ok(b))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            let state = CChainState::<F>::from_bits(cs.namespace(|| "genesis"), &payload)?;
+            let computed = state.merkle_root_hash(cs.namespace(|| "root hash"))?;
+
+            let root_hash = ReachCircuit::<DEFAULT_ACCOUNTS>::genesis_root()
+                .iter()
+                .map(|byte| (0..8).map(move |i| Boolean::constant((byte >> i) & 1u8 == 1u8)))
+                .flatten()
+                .collect::<Vec<_>>();
+            enforce_equality(
+                cs.namespace(|| "match genesis root"),
+                &to_sha256_bits(&root_hash),
+                &computed,
+            );
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn genesis_root_matches_base_payload() {
+        let circuit = ReachCircuit::new([3u8; 32]);
+        assert!(circuit.is_genesis(&RecursiveCircuit::<Fp>::base_payload(&circuit)));
+
+        let minted = ChainState::<DEFAULT_ACCOUNTS>::genesis([3u8; 32]).apply_tx(Transaction {
+            from: 0,
+            to: 0,
+            asset_id: 0,
+            amount: 1,
+            unlock_height: 0,
+            kind: TxKind::Mint,
+        });
+        assert!(!circuit.is_genesis(&minted.to_bits()));
+        assert!(
+            !ReachCircuit::<DEFAULT_ACCOUNTS>::new([4u8; 32])
+                .is_genesis(&RecursiveCircuit::<Fp>::base_payload(&circuit))
+        );
+
+        let circuit = GenesisRoot { circuit };
+        assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+    }
+
+    #[test]
+    fn payload_height_counts_txs_and_empty_blocks() {
+        let preimage = [7u8; 32];
+        let mut authority_hash = [0u8; 32];
+        authority_hash.copy_from_slice(&Sha256::digest(&preimage));
+        let circuit = || {
+            ReachCircuit::<DEFAULT_ACCOUNTS>::new(authority_hash)
+                .with_mint_preimage(preimage)
+                .allowing_empty_blocks()
+        };
+        let genesis = ChainState::<DEFAULT_ACCOUNTS>::genesis(authority_hash);
+        assert_eq!(
+            circuit().payload_height(&genesis.clone().to_bits()),
+            Some(0)
+        );
+
+        let mut state = genesis;
+        for _ in 0..3 {
+            let next = state.empty_block();
+            assert!(is_valid_transition(circuit(), &state, &next));
+            state = next;
+        }
+        assert_eq!(circuit().payload_height(&state.clone().to_bits()), Some(3));
+
+        // One more transition, but four transactions
+        let mint = |to| Transaction {
+            from: to,
+            to,
+            asset_id: 0,
+            amount: 10,
+            unlock_height: 0,
+            kind: TxKind::Mint,
+        };
+        let next = (0..4)
+            .fold(ChainStateBuilder::new(&state), |builder, to| {
+                builder.tx(mint(to))
+            })
+            .build()
+            .expect("build");
+        assert!(is_valid_transition(circuit(), &state, &next));
+
+        let payload = next.to_bits();
+        assert_eq!(circuit().payload_height(&payload), Some(7));
+        assert_eq!(circuit().payload_height(&payload[1..]), None);
+    }
+
+    #[test]
+    fn payload_minted_sums_mints_per_asset() {
+        let mint = |asset_id, amount| Transaction {
+            from: 2,
+            to: 2,
+            asset_id,
+            amount,
+            unlock_height: 0,
+            kind: TxKind::Mint,
+        };
+        let prev_state = state_with_assets::<8, 2>(1, [[0; 2]; 8], &[]);
+        let curr_state = ChainStateBuilder::new(&prev_state)
+            .tx(mint(0, 30))
+            .tx(Transaction {
+                from: 2,
+                to: 3,
+                asset_id: 0,
+                amount: 10,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            })
+            .tx(mint(1, 7))
+            .tx(mint(0, 5))
+            .build()
+            .expect("build");
+
+        let circuit = ReachCircuit::<8, 2>::new([0u8; 32]);
+        assert_eq!(
+            circuit.payload_minted(&curr_state.clone().to_bits()),
+            Some([35, 7])
+        );
+        assert_eq!(circuit.payload_minted(&prev_state.to_bits()), Some([0, 0]));
+        assert_eq!(circuit.payload_minted(&[false; 8]), None);
+    }
+
+    #[test]
+    fn check_transition_mirrors_circuit() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 40,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let curr_state = prev_state.apply_tx(tx);
+        assert_eq!(check_transition(&prev_state, &curr_state), Ok(()));
+        assert!(is_valid_transition(
+            ReachCircuit::new([0u8; 32]),
+            &prev_state,
+            &curr_state
+        ));
+        assert_eq!(
+            check_transition(&prev_state, &prev_state.empty_block()),
+            Ok(())
+        );
+
+        let mut skipped = curr_state.clone();
+        skipped.height += 1;
+        assert_eq!(
+            check_transition(&prev_state, &skipped),
+            Err(TransitionError::Height)
+        );
+
+        let mut stale_root = curr_state.clone();
+        stale_root.root_hash = prev_state.root_hash.clone();
+        assert_eq!(
+            check_transition(&prev_state, &stale_root),
+            Err(TransitionError::RootHash)
+        );
+
+        let mut balances = curr_state.balances;
+        balances[1][0] += 1;
+        let inflated = state_with_assets(2, balances, &[tx]);
+        assert_eq!(
+            check_transition(&prev_state, &inflated),
+            Err(TransitionError::Balances)
+        );
+        assert!(!is_valid_transition(
+            ReachCircuit::new([0u8; 32]),
+            &prev_state,
+            &inflated
+        ));
+
+        let overdraw = Transaction { amount: 101, ..tx };
+        let mut balances = prev_state.balances;
+        balances[0][0] = balances[0][0].wrapping_sub(overdraw.amount);
+        balances[1][0] += overdraw.amount;
+        let overdrawn = state_with_assets(2, balances, &[overdraw]);
+        assert_eq!(
+            check_transition(&prev_state, &overdrawn),
+            Err(TransitionError::Build(BuildError::InsufficientBalance))
+        );
+    }
+
+    struct Payload {
+        bits: Vec<bool>,
+    }
+
+    impl<F: Field> Circuit<F> for Payload {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = self
+                .bits
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| {
+                    AllocatedBit::alloc(cs.namespace(|| format!("payload bit {}", i)), || Ok(b))
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+
+            CChainState::<F>::from_bits(cs.namespace(|| "state"), &bits).map(|_| ())
+        }
+    }
+
+    #[test]
+    fn payload_must_have_exact_length() {
+        let bits = ChainState::<DEFAULT_ACCOUNTS>::genesis([0u8; 32]).to_bits();
+
+        let circuit = Payload { bits: bits.clone() };
+        assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+
+        // A root hash one byte short
+        let mut short = bits.clone();
+        short.drain(ROOT_HASH_OFFSET..ROOT_HASH_OFFSET + 8);
+        let circuit = Payload { bits: short };
+        match is_satisfied::<Fp, _>(&circuit, &[]) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected short payload to be rejected"),
+        }
+
+        let mut long = bits;
+        long.push(false);
+        let circuit = Payload { bits: long };
+        match is_satisfied::<Fp, _>(&circuit, &[]) {
+            Err(SynthesisError::Unsatisfiable) => (),
+            _ => panic!("expected long payload to be rejected"),
+        }
+    }
+
+    #[test]
+    fn height_cannot_pass_u64_max() {
+        let tx = Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 10,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        let prev_state = state_with(u64::MAX - 1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let curr_state = prev_state.apply_tx(tx);
+        assert_eq!(curr_state.height, u64::MAX);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        assert_eq!(
+            ChainStateBuilder::new(&curr_state)
+                .tx(tx)
+                .build()
+                .unwrap_err(),
+            BuildError::HeightOverflow
+        );
+
+        // The same block claimed with the height wrapped around to zero
+        let wrapped = state_with(0, [80, 20, 0, 0, 0, 0, 0, 0], &[tx]);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &curr_state, &wrapped));
+    }
+
+    #[test]
+    fn burn_destroys_sender_funds() {
+        let preimage = [7u8; 32];
+        let mut authority_hash = [0u8; 32];
+        authority_hash.copy_from_slice(&Sha256::digest(&preimage));
+
+        let genesis = ChainState::<8>::genesis(authority_hash);
+        let minted = genesis.apply_tx(Transaction {
+            from: 0,
+            to: 0,
+            asset_id: 0,
+            amount: 500,
+            unlock_height: 0,
+            kind: TxKind::Mint,
+        });
+        let burn = Transaction {
+            from: 0,
+            to: BURN_ACCOUNT,
+            asset_id: 0,
+            amount: 500,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+        let burned = minted.apply_tx(burn);
+        assert_eq!(burned.balances[0], [0]);
+        assert_eq!(burned.root_hash, genesis.root_hash);
+
+        // Burning needs no mint authority
+        let circuit = ReachCircuit::new(authority_hash);
+        assert!(is_valid_transition(circuit, &minted, &burned));
+
+        // Claiming the burn while keeping the funds
+        let mut kept = minted.clone();
+        kept.height += 1;
+        kept.txs = vec![burn];
+        let circuit = ReachCircuit::new(authority_hash);
+        assert!(!is_valid_transition(circuit, &minted, &kept));
+
+        assert_eq!(
+            ChainStateBuilder::new(&burned)
+                .tx(burn)
+                .build()
+                .unwrap_err(),
+            BuildError::InsufficientBalance
+        );
+    }
+
+    // Only the supply constraint, without the per-account checks that would
+    // otherwise catch the same states first
+    struct Supply {
+        prev_state: ChainState,
+        curr_state: ChainState,
+    }
+
+    impl<F: Field> Circuit<F> for Supply {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let prev_bits = self
+                .prev_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "prev payload"))?;
+            let curr_bits = self
+                .curr_state
+                .clone()
+                .alloc_bits(cs.namespace(|| "curr payload"))?;
+            let prev_state = CChainState::<F>::from_bits(cs.namespace(|| "prev"), &prev_bits)?;
+            let curr_state = CChainState::<F>::from_bits(cs.namespace(|| "curr"), &curr_bits)?;
+
+            curr_state.enforce_supply_change(cs.namespace(|| "supply"), &prev_state);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn total_supply_only_changes_by_mints_and_burns() {
+        let supply_holds = |prev_state: &ChainState, curr_state: &ChainState| {
+            let circuit = Supply {
+                prev_state: prev_state.clone(),
+                curr_state: curr_state.clone(),
+            };
+            is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize")
+        };
+        // Account 0 only ever sends to itself to mint
+        let tx = |to, amount| Transaction {
+            from: 0,
+            to,
+            asset_id: 0,
+            amount,
+            unlock_height: 0,
+            kind: if to == 0 {
+                TxKind::Mint
+            } else {
+                TxKind::Transfer
+            },
+        };
+
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        for &(to, amount) in &[(1, 30), (0, 50), (BURN_ACCOUNT, 20)] {
+            let curr_state = prev_state.apply_tx(tx(to, amount));
+            assert!(supply_holds(&prev_state, &curr_state));
+        }
+
+        // The receiver is credited more than the sender paid
+        let inflated = state_with(2, [70, 40, 0, 0, 0, 0, 0, 0], &[tx(1, 30)]);
+        assert!(!supply_holds(&prev_state, &inflated));
+
+        // A burn that destroys less than it claims
+        let kept = state_with(2, [90, 0, 0, 0, 0, 0, 0, 0], &[tx(BURN_ACCOUNT, 20)]);
+        assert!(!supply_holds(&prev_state, &kept));
+    }
+
+    struct Inclusion {
+        state: ChainState,
+        index: usize,
+        path: Vec<[u8; 32]>,
+    }
+
+    impl<F: Field> Circuit<F> for Inclusion {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = |bytes: &[u8]| {
+                bytes
+                    .iter()
+                    .map(|byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8))
+                    .flatten()
+                    .collect::<Vec<_>>()
+            };
+            let mut alloc = |name: String, bytes: &[u8]| {
+                bits(bytes)
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, b)| {
+                        AllocatedBit::alloc(cs.namespace(|| format!("{} bit {}", name, i)), || {
+                            Ok(b)
+                        })
+                        .map(Boolean::from)
+                    })
+                    .collect::<Result<Vec<_>, _>>()
+            };
+
+            let account_bits = alloc(
+                "account".to_string(),
+                &account_bytes(&self.state.balances[self.index]),
+            )?;
+            let path = self
+                .path
+                .iter()
+                .enumerate()
+                .map(|(level, sibling)| alloc(format!("sibling {}", level), sibling))
+                .map(|sibling| sibling.map(|bits| to_sha256_bits(&bits)))
+                .collect::<Result<Vec<_>, _>>()?;
+            let root_hash = bits(&self.state.root_hash)
+                .into_iter()
+                .map(Boolean::constant)
+                .collect::<Vec<_>>();
+
+            merkle_inclusion(
+                cs.namespace(|| "inclusion"),
+                &account_bits,
+                self.index,
+                &path,
+                &root_hash,
+            )
+        }
+    }
+
+    #[test]
+    fn merkle_inclusion_matches_full_root() {
+        let state = state_with(1, [5, 10, 15, 20, 25, 30, 35, 40], &[]);
+        for &index in &[0, 3, 7] {
+            let path = merkle_path(&state.balances, index);
+            assert_eq!(path.len(), 3);
+
+            // Folding the path natively gives the full root
+            let leaf = Sha256::digest(&account_bytes(&state.balances[index])).to_vec();
+            let root = path
+                .iter()
+                .enumerate()
+                .fold(leaf, |node, (level, sibling)| {
+                    if (index >> level) & 1 == 0 {
+                        hash_two(&node, &sibling.to_vec())
+                    } else {
+                        hash_two(&sibling.to_vec(), &node)
+                    }
+                });
+            assert_eq!(root, merkle_root(&state.balances).to_vec());
+
+            let circuit = Inclusion {
+                state: state.clone(),
+                index,
+                path,
+            };
+            assert!(is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+        }
+
+        // A path for one account doesn't prove another
+        let circuit = Inclusion {
+            state: state.clone(),
+            index: 2,
+            path: merkle_path(&state.balances, 3),
+        };
+        assert!(!is_satisfied::<Fp, _>(&circuit, &[]).expect("synthesize"));
+    }
+
+    struct TxFields {
+        state: ChainState,
+        tx: Transaction,
+    }
+
+    impl<F: Field> Circuit<F> for TxFields {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = self
+                .state
+                .clone()
+                .alloc_bits(cs.namespace(|| "state bits"))?;
+            let state = CChainState::<F>::from_bits(cs.namespace(|| "state"), &bits)?;
+
+            // The amount's decoding is covered by `TxAmount`
+            let tx = &state.txs[0];
+            if (tx.from, tx.to, tx.asset_id) != (self.tx.from, self.tx.to, self.tx.asset_id)
+                || tx.unlock_height.get_value() != Some(F::from_u64(self.tx.unlock_height))
+            {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transaction_survives_bits_round_trip() {
+        let tx = Transaction {
+            from: 0x0102,
+            to: 7,
+            asset_id: 0,
+            amount: u128::MAX - 0x0304,
+            unlock_height: 0x0506,
+            kind: TxKind::Transfer,
+        };
+        assert_eq!(Transaction::decode(&tx.encode()), Some(tx));
+        assert_eq!(Transaction::decode(&tx.encode()[1..]), None);
+        assert_eq!(tx.encode()[..2], [0x02, 0x01]);
+
+        let state = state_with::<8>(1, [u128::MAX; 8], &[tx]);
+        let bytes = bits_to_bytes(&state.clone().to_bits());
+        assert_eq!(bytes_to_bits(&bytes), state.clone().to_bits());
+
+        let txs_offset = CChainState::<Fp>::TXS_OFFSET / 8;
+        assert_eq!(
+            Transaction::decode(&bytes[txs_offset..txs_offset + TX_BYTES]),
+            Some(tx)
+        );
+        assert!(is_satisfied::<Fp, _>(&TxFields { state, tx }, &[]).unwrap());
+    }
+
+    #[test]
+    fn locked_transaction_waits_for_unlock_height() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let tx = |unlock_height| Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 40,
+            unlock_height,
+            kind: TxKind::Transfer,
+        };
+
+        // Applying one transaction moves the chain to height 2
+        let curr_state = prev_state.apply_tx(tx(2));
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state)
+                .tx(tx(3))
+                .build()
+                .unwrap_err(),
+            BuildError::Locked
+        );
+
+        let mut locked = curr_state.clone();
+        locked.txs = vec![tx(3)];
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &locked));
+    }
+
+    #[test]
+    fn incremental_merkle_matches_full_recompute() {
+        let mut balances = [[0u128; 2]; 7];
+        let mut tree = IncrementalMerkle::new(&balances);
+        assert_eq!(tree.root(), merkle_root(&balances));
+
+        // The odd last account pairs with itself on every layer
+        for &(index, balance) in &[(0, [5, 0]), (6, [1, 2]), (3, [0, 9]), (6, [0, 0])] {
+            balances[index] = balance;
+            assert_eq!(tree.update_leaf(index, balance), merkle_root(&balances));
+        }
+
+        let mut single = IncrementalMerkle::<1, 2>::new(&[[0; 2]]);
+        assert_eq!(single.update_leaf(0, [3, 4]), merkle_root(&[[3, 4]]));
+    }
+
+    struct TxCount {
+        state: ChainState,
+        count: usize,
+    }
+
+    impl<F: Field> Circuit<F> for TxCount {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let bits = self
+                .state
+                .clone()
+                .alloc_bits(cs.namespace(|| "state bits"))?;
+            let state = CChainState::<F>::from_bits(cs.namespace(|| "state"), &bits)?;
+            if state.txs().len() != self.count {
+                return Err(SynthesisError::Unsatisfiable);
+            }
+
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn no_tx_and_zero_tx_encode_differently() {
+        // The tx count byte, not the padding, says which slots hold a tx
+        let zero_tx = Transaction {
+            from: 0,
+            to: 0,
+            asset_id: 0,
+            amount: 0,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+        let empty = state_with::<8>(1, [0; 8], &[]);
+        let zero = state_with::<8>(1, [0; 8], &[zero_tx]);
+
+        let (empty_bits, zero_bits) = (empty.clone().to_bits(), zero.clone().to_bits());
+        assert_eq!(empty_bits.len(), zero_bits.len());
+        assert_ne!(empty_bits, zero_bits);
+
+        let txs_offset = CChainState::<Fp>::TXS_OFFSET / 8;
+        let bytes = bits_to_bytes(&zero_bits);
+        assert_eq!(bytes[txs_offset - 1], 1);
+        assert_eq!(
+            Transaction::decode(&bytes[txs_offset..txs_offset + TX_BYTES]),
+            Some(zero_tx)
+        );
+        assert_eq!(bits_to_bytes(&empty_bits)[txs_offset - 1], 0);
+
+        let decodes_to = |state, count| is_satisfied::<Fp, _>(&TxCount { state, count }, &[]);
+        assert!(decodes_to(empty, 0).unwrap());
+        assert!(decodes_to(zero, 1).unwrap());
+    }
+
+    #[test]
+    fn proof_range_starts_from_given_height() {
+        let start = state_with::<8>(10, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let circuit = || ReachCircuit::new([0u8; 32]).starting_from(start.clone());
+        assert_eq!(
+            RecursiveCircuit::<Fp>::base_payload(&circuit()),
+            start.clone().to_bits()
+        );
+
+        let mut state = start.clone();
+        for to in 1..4 {
+            let next = state.apply_tx(Transaction {
+                from: 0,
+                to,
+                asset_id: 0,
+                amount: 10,
+                unlock_height: 0,
+                kind: TxKind::Transfer,
+            });
+            assert!(is_valid_transition(circuit(), &state, &next));
+            state = next;
+        }
+
+        let payload = state.to_bits();
+        assert_eq!(circuit().payload_heights(&payload), Some((10, 13)));
+        assert_eq!(
+            ReachCircuit::<8>::new([0u8; 32]).payload_heights(&payload),
+            Some((0, 13))
+        );
+        assert_eq!(circuit().payload_heights(&payload[1..]), None);
+    }
+
+    #[test]
+    fn self_transfer_keeps_balance_and_supply() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let to_self = Transaction {
+            from: 0,
+            to: 0,
+            asset_id: 0,
+            amount: 40,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        };
+
+        // No mint preimage, so nothing may be minted
+        let curr_state = prev_state.apply_tx(to_self);
+        assert_eq!(curr_state.balances, prev_state.balances);
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(is_valid_transition(circuit, &prev_state, &curr_state));
+        assert_eq!(
+            ReachCircuit::<8>::new([0u8; 32]).payload_minted(&curr_state.clone().to_bits()),
+            Some([0])
+        );
+
+        // Crediting the amount as if it were minted
+        let mut minted = curr_state.clone();
+        minted.balances[0][0] += 40;
+        minted.root_hash = minted.merkle_root_hash();
+        let circuit = ReachCircuit::new([0u8; 32]);
+        assert!(!is_valid_transition(circuit, &prev_state, &minted));
+
+        // Still bounded by the balance, like any transfer
+        let overdraw = Transaction {
+            amount: 101,
+            ..to_self
+        };
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state)
+                .tx(overdraw)
+                .build()
+                .unwrap_err(),
+            BuildError::InsufficientBalance
+        );
+
+        let mint_to_other = Transaction {
+            to: 1,
+            kind: TxKind::Mint,
+            ..to_self
+        };
+        assert_eq!(
+            ChainStateBuilder::new(&prev_state)
+                .tx(mint_to_other)
+                .build()
+                .unwrap_err(),
+            BuildError::InvalidMint
+        );
+    }
+
+    #[test]
+    fn touched_accounts_must_match_previous_root() {
+        let prev_state = state_with(1, [100, 0, 0, 0, 0, 0, 0, 0], &[]);
+        let curr_state = prev_state.apply_tx(Transaction {
+            from: 0,
+            to: 1,
+            asset_id: 0,
+            amount: 30,
+            unlock_height: 0,
+            kind: TxKind::Transfer,
+        });
+        assert!(is_valid_transition(
+            ReachCircuit::new([0u8; 32]),
+            &prev_state,
+            &curr_state
+        ));
+
+        // A previous root the sender's balance isn't under
+        let mut forged = prev_state.clone();
+        forged.root_hash = merkle_root(&single_asset([500, 0, 0, 0, 0, 0, 0, 0])).to_vec();
+        assert!(!is_valid_transition(
+            ReachCircuit::new([0u8; 32]),
+            &forged,
+            &curr_state
+        ));
+    }
 }
@@ -0,0 +1,122 @@
+use halo::{ConstraintSystem, Field, LinearCombination, SynthesisError, Variable};
+
+// Counts multiplication gates and linear constraints per top-level namespace,
+// so `ReachCircuit` can report which phase of a transition dominates.
+// Constraints made outside any namespace are reported as "unscoped".
+pub struct Profiler<'a, CS> {
+    cs: &'a mut CS,
+    depth: usize,
+    phase: String,
+    count: usize,
+    unscoped: usize,
+    total: usize,
+    phases: Vec<(String, usize)>,
+}
+
+impl<'a, CS> Profiler<'a, CS> {
+    pub fn new(cs: &'a mut CS) -> Self {
+        Profiler {
+            cs,
+            depth: 0,
+            phase: String::new(),
+            count: 0,
+            unscoped: 0,
+            total: 0,
+            phases: vec![],
+        }
+    }
+
+    pub fn phases(&self) -> &[(String, usize)] {
+        &self.phases
+    }
+
+    pub fn unscoped(&self) -> usize {
+        self.unscoped
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn log_phases(&self) {
+        for (phase, count) in self.phases() {
+            log::info!("{}: {} constraints", phase, count);
+        }
+        log::info!("unscoped: {} constraints", self.unscoped());
+        log::info!("total: {} constraints", self.total());
+    }
+
+    fn record(&mut self) {
+        self.total += 1;
+        if self.depth == 0 {
+            self.unscoped += 1;
+        } else {
+            self.count += 1;
+        }
+    }
+}
+
+impl<'a, F: Field, CS: ConstraintSystem<F>> ConstraintSystem<F> for Profiler<'a, CS> {
+    type Root = Self;
+
+    const ONE: Variable = CS::ONE;
+
+    fn alloc<V, A, AR>(&mut self, annotation: A, value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc(annotation, value)
+    }
+
+    fn alloc_input<V, A, AR>(&mut self, annotation: A, value: V) -> Result<Variable, SynthesisError>
+    where
+        V: FnOnce() -> Result<F, SynthesisError>,
+        A: FnOnce() -> AR,
+        AR: Into<String>,
+    {
+        self.cs.alloc_input(annotation, value)
+    }
+
+    fn enforce_zero(&mut self, lc: LinearCombination<F>) {
+        self.record();
+        self.cs.enforce_zero(lc)
+    }
+
+    fn multiply<V>(&mut self, values: V) -> Result<(Variable, Variable, Variable), SynthesisError>
+    where
+        V: FnOnce() -> Result<(F, F, F), SynthesisError>,
+    {
+        self.record();
+        self.cs.multiply(values)
+    }
+
+    fn push_namespace<NR, N>(&mut self, name_fn: N)
+    where
+        NR: Into<String>,
+        N: FnOnce() -> NR,
+    {
+        let name = name_fn().into();
+        if self.depth == 0 {
+            self.phase = name.clone();
+            self.count = 0;
+        }
+        self.depth += 1;
+
+        self.cs.get_root().push_namespace(|| name)
+    }
+
+    fn pop_namespace(&mut self, gadget_name: Option<String>) {
+        self.depth -= 1;
+        if self.depth == 0 {
+            self.phases.push((self.phase.clone(), self.count));
+        }
+
+        self.cs.get_root().pop_namespace(gadget_name)
+    }
+
+    fn get_root(&mut self) -> &mut Self::Root {
+        self
+    }
+}
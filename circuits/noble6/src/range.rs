@@ -0,0 +1,156 @@
+// Range checks for the halo circuits in this crate. The bellman circuits
+// range check through their own bit decompositions instead.
+use halo::{
+    unpack_fe, AllocatedBit, AllocatedNum, Boolean, ConstraintSystem, Field, SynthesisError,
+};
+
+use crate::lc_from_bits;
+
+// Decompose the number into `bits` bits, which only holds if it is below 2^bits
+pub fn enforce_range<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    num: &AllocatedNum<F>,
+    bits: usize,
+) -> Result<(), SynthesisError> {
+    let values = num.get_value().map(|value| unpack_fe(&value, bits));
+
+    let num_bits = (0..bits)
+        .map(|i| {
+            AllocatedBit::alloc(cs.namespace(|| format!("bit {}", i)), || {
+                values
+                    .as_ref()
+                    .map(|values| values[i])
+                    .ok_or(SynthesisError::AssignmentMissing)
+            })
+            .map(Boolean::from)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let bits_lc = lc_from_bits::<F, CS>(&num_bits);
+    cs.enforce_zero(bits_lc - &num.lc());
+
+    Ok(())
+}
+
+// Both `a` and `b` must already be known to fit in `bits` bits. Nothing in
+// `ReachCircuit` needs a strict bound yet.
+#[cfg_attr(not(test), allow(dead_code))]
+pub fn enforce_less_than<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    a: &AllocatedNum<F>,
+    b: &AllocatedNum<F>,
+    bits: usize,
+) -> Result<(), SynthesisError> {
+    let diff = AllocatedNum::alloc(cs.namespace(|| "b - a - 1"), || {
+        match (a.get_value(), b.get_value()) {
+            (Some(a), Some(b)) => Ok(b - a - F::one()),
+            _ => Err(SynthesisError::AssignmentMissing),
+        }
+    })?;
+    cs.enforce_zero(b.lc() - &a.lc() - CS::ONE - &diff.lc());
+
+    enforce_range(cs.namespace(|| "b - a - 1 range"), &diff, bits)
+}
+
+// Both `a` and `b` must already be known to fit in `bits` bits
+pub fn enforce_less_or_equal<F: Field, CS: ConstraintSystem<F>>(
+    mut cs: CS,
+    a: &AllocatedNum<F>,
+    b: &AllocatedNum<F>,
+    bits: usize,
+) -> Result<(), SynthesisError> {
+    let diff = AllocatedNum::alloc(cs.namespace(|| "b - a"), || {
+        match (a.get_value(), b.get_value()) {
+            (Some(a), Some(b)) => Ok(b - a),
+            _ => Err(SynthesisError::AssignmentMissing),
+        }
+    })?;
+    cs.enforce_zero(b.lc() - &a.lc() - &diff.lc());
+
+    enforce_range(cs.namespace(|| "b - a range"), &diff, bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use halo::{is_satisfied, Circuit, Fp};
+
+    struct Range {
+        value: u64,
+        bits: usize,
+    }
+
+    impl<F: Field> Circuit<F> for Range {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let num = AllocatedNum::alloc(cs.namespace(|| "num"), || Ok(F::from_u64(self.value)))?;
+            enforce_range(cs.namespace(|| "range"), &num, self.bits)
+        }
+    }
+
+    struct LessThan {
+        a: u64,
+        b: u64,
+        bits: usize,
+    }
+
+    impl<F: Field> Circuit<F> for LessThan {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(F::from_u64(self.a)))?;
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(F::from_u64(self.b)))?;
+            enforce_less_than(cs.namespace(|| "a < b"), &a, &b, self.bits)
+        }
+    }
+
+    struct LessOrEqual {
+        a: u64,
+        b: u64,
+        bits: usize,
+    }
+
+    impl<F: Field> Circuit<F> for LessOrEqual {
+        fn synthesize<CS: ConstraintSystem<F>>(&self, cs: &mut CS) -> Result<(), SynthesisError> {
+            let a = AllocatedNum::alloc(cs.namespace(|| "a"), || Ok(F::from_u64(self.a)))?;
+            let b = AllocatedNum::alloc(cs.namespace(|| "b"), || Ok(F::from_u64(self.b)))?;
+            enforce_less_or_equal(cs.namespace(|| "a <= b"), &a, &b, self.bits)
+        }
+    }
+
+    #[test]
+    fn range_boundaries() {
+        let in_range = |value, bits| is_satisfied::<Fp, _>(&Range { value, bits }, &[]).unwrap();
+
+        assert!(in_range(0, 8));
+        assert!(in_range((1 << 8) - 1, 8));
+        assert!(!in_range(1 << 8, 8));
+
+        assert!(in_range(0, 64));
+        assert!(in_range(u64::max_value(), 64));
+        assert!(!in_range(1 << 32, 32));
+    }
+
+    #[test]
+    fn less_than_boundaries() {
+        let less_than = |a, b| is_satisfied::<Fp, _>(&LessThan { a, b, bits: 8 }, &[]).unwrap();
+
+        assert!(less_than(0, 1));
+        assert!(less_than(254, 255));
+        assert!(less_than(0, 255));
+        assert!(!less_than(1, 1));
+        assert!(!less_than(2, 1));
+        assert!(!less_than(255, 0));
+    }
+
+    #[test]
+    fn less_or_equal_boundaries() {
+        let less_or_equal =
+            |a, b| is_satisfied::<Fp, _>(&LessOrEqual { a, b, bits: 8 }, &[]).unwrap();
+
+        assert!(less_or_equal(0, 0));
+        assert!(less_or_equal(1, 1));
+        assert!(less_or_equal(254, 255));
+        assert!(less_or_equal(255, 255));
+        assert!(!less_or_equal(2, 1));
+        assert!(!less_or_equal(255, 0));
+    }
+}
@@ -4,51 +4,89 @@ use bellman::{
         multipack,
         sha256::sha256,
     },
-    groth16, Circuit, ConstraintSystem, SynthesisError,
+    groth16, Circuit, ConstraintSystem, LinearCombination, SynthesisError,
 };
-use pairing::bls12_381::Bls12;
+use ff::Field;
+use gadgets::{u128_to_circuit_bits, TWIN_NOTE_DOMAIN};
+use pairing::bls12_381::{Bls12, Fr};
 use pairing::Engine;
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::wasm_bindgen;
+#[cfg(feature = "zeroize")]
+use zeroize::Zeroize;
 
-fn convert_to_bits(num: u128) -> Vec<bool> {
-    num.to_be_bytes()
-        .into_iter()
-        .map(|byte| (0..8).map(move |i| (byte >> i) & 1u8 == 1u8).rev())
-        .flatten()
-        .collect()
+pub mod verify_core;
+
+// Native counterpart of `hash_amount`
+fn note_hash(pubkey: &[u8; 32], amount: u128, nonce: u128) -> Vec<u8> {
+    let mut preimage = TWIN_NOTE_DOMAIN.to_vec();
+    preimage.extend(pubkey);
+    preimage.extend(&amount.to_be_bytes());
+    preimage.extend(&nonce.to_be_bytes());
+
+    Sha256::digest(&preimage).to_vec()
 }
 
+// The owner's pubkey is part of the preimage, so a note hash commits to who
+// holds the note as well as to its amount. Returns the hash and the allocated
+// amount bits, most significant first.
 fn hash_amount<E: Engine, CS: ConstraintSystem<E>>(
     mut cs: CS,
+    pubkey: &[u8; 32],
     amount: u128,
     nonce: u128,
-) -> Result<Vec<Boolean>, SynthesisError> {
-    let amount_bits = convert_to_bits(amount);
-    let nonce_bits = convert_to_bits(nonce);
+) -> Result<(Vec<Boolean>, Vec<Boolean>), SynthesisError> {
+    let mut preimage = multipack::bytes_to_bits(pubkey);
+    preimage.extend(&u128_to_circuit_bits(amount));
+    preimage.extend(&u128_to_circuit_bits(nonce));
 
-    let mut preimage = [false; 256];
-    for i in 0..128 {
-        preimage[i] = amount_bits[i];
-        preimage[i + 128] = nonce_bits[i];
+    let mut preimage_bits = multipack::bytes_to_bits(&TWIN_NOTE_DOMAIN)
+        .into_iter()
+        .map(Boolean::constant)
+        .collect::<Vec<_>>();
+    for (i, b) in preimage.iter().enumerate() {
+        let bit = AllocatedBit::alloc(cs.namespace(|| format!("preimage bits {}", i)), Some(*b))?;
+        preimage_bits.push(Boolean::from(bit));
     }
 
-    let preimage_bits = preimage
-        .into_iter()
-        .enumerate()
-        .map(|(i, b)| {
-            AllocatedBit::alloc(cs.namespace(|| format!("preimage bits {}", i)), Some(*b))
-        })
-        .map(|b| b.map(Boolean::from))
-        .collect::<Result<Vec<_>, _>>()?;
+    let hash = sha256(
+        cs.namespace(|| "sha256(domain + pubkey + amount + nonce)"),
+        &preimage_bits,
+    )?;
 
-    sha256(cs.namespace(|| "sha256(amount + nonce)"), &preimage_bits)
+    // After the 128 domain bits and 256 pubkey bits
+    Ok((hash, preimage_bits[384..512].to_vec()))
 }
 
 struct Twin {
+    from_pubkey: [u8; 32],
+    to_pubkey: [u8; 32],
     input_amount: u128,
     input_nonce: u128,
     output_amount: u128,
     output_nonce: u128,
+    require_equal: bool,
+}
+
+// The circuit holds a copy of every witness field until proving drops it
+#[cfg(feature = "zeroize")]
+impl Drop for Twin {
+    fn drop(&mut self) {
+        self.from_pubkey.zeroize();
+        self.to_pubkey.zeroize();
+        self.input_amount.zeroize();
+        self.input_nonce.zeroize();
+        self.output_amount.zeroize();
+        self.output_nonce.zeroize();
+        self.require_equal.zeroize();
+    }
 }
 
 impl<E: Engine> Circuit<E> for Twin {
@@ -56,15 +94,54 @@ impl<E: Engine> Circuit<E> for Twin {
         if self.input_amount < self.output_amount {
             return Err(SynthesisError::Unsatisfiable);
         }
+        if self.require_equal && self.input_amount != self.output_amount {
+            return Err(SynthesisError::Unsatisfiable);
+        }
 
-        let mut input_output_hashes = hash_amount(&mut cs, self.input_amount, self.input_nonce)?;
-        let output_amount_hash = hash_amount(&mut cs, self.output_amount, self.output_nonce)?;
+        let (mut input_output_hashes, input_amount_bits) = hash_amount(
+            &mut cs,
+            &self.from_pubkey,
+            self.input_amount,
+            self.input_nonce,
+        )?;
+        let (output_amount_hash, output_amount_bits) = hash_amount(
+            &mut cs,
+            &self.to_pubkey,
+            self.output_amount,
+            self.output_nonce,
+        )?;
         input_output_hashes.extend(output_amount_hash);
 
+        let require_equal = Boolean::from(AllocatedBit::alloc(
+            cs.namespace(|| "require equal"),
+            Some(self.require_equal),
+        )?);
+
+        // Both amounts fit in 128 bits, so their packed difference can't wrap
+        let mut amount_diff = LinearCombination::zero();
+        let mut coeff = E::Fr::one();
+        for (input_bit, output_bit) in input_amount_bits
+            .iter()
+            .rev()
+            .zip(output_amount_bits.iter().rev())
+        {
+            amount_diff = amount_diff + &input_bit.lc(CS::one(), coeff);
+            amount_diff = amount_diff - &output_bit.lc(CS::one(), coeff);
+            coeff.double();
+        }
+        cs.enforce(
+            || "require equal => input amount = output amount",
+            |lc| lc + &amount_diff,
+            |lc| lc + &require_equal.lc(CS::one(), E::Fr::one()),
+            |lc| lc,
+        );
+
         multipack::pack_into_inputs(
             cs.namespace(|| "input + output amount hashes"),
             &input_output_hashes,
-        )
+        )?;
+
+        multipack::pack_into_inputs(cs.namespace(|| "require equal"), &[require_equal])
     }
 }
 
@@ -87,28 +164,84 @@ impl<E: Engine> Params<E> {
         let p = groth16::Parameters::read(bytes, true)?;
         Ok(Params(p))
     }
+
+    pub fn save(&self, path: &Path) -> std::io::Result<()> {
+        self.0.write(BufWriter::new(File::create(path)?))
+    }
+
+    pub fn load(path: &Path) -> std::io::Result<Params<E>> {
+        let p = groth16::Parameters::read(BufReader::new(File::open(path)?), true)?;
+        Ok(Params(p))
+    }
 }
 
-pub fn trust_setup() -> Params<Bls12> {
+fn setup<R: RngCore>(rng: &mut R) -> Params<Bls12> {
     let params = {
         let c = Twin {
+            from_pubkey: [0u8; 32],
+            to_pubkey: [0u8; 32],
             input_amount: 0,
             input_nonce: 0,
             output_amount: 0,
             output_nonce: 0,
+            require_equal: false,
         };
-        groth16::generate_random_parameters::<Bls12, _, _>(c, &mut OsRng).expect("setup")
+        groth16::generate_random_parameters::<Bls12, _, _>(c, rng).expect("setup")
     };
 
     Params(params)
 }
 
-#[derive(Debug, Clone, Copy)]
+// Params are serialized with the verifying key first, so the proving key
+// never needs to be parsed
+pub fn verifying_key_from_params_bytes(bytes: &[u8]) -> Result<Vec<u8>, std::io::Error> {
+    let vk = groth16::VerifyingKey::<Bls12>::read(bytes)?;
+
+    let mut vk_bytes = Vec::new();
+    vk.write(&mut vk_bytes)?;
+    Ok(vk_bytes)
+}
+
+pub fn trust_setup() -> Params<Bls12> {
+    setup(&mut OsRng)
+}
+
+pub fn trust_setup_from_seed(seed: [u8; 32]) -> Params<Bls12> {
+    setup(&mut ChaCha20Rng::from_seed(seed))
+}
+
+#[derive(Debug, Clone)]
 pub struct Witness {
+    pub from_pubkey: [u8; 32],
+    pub to_pubkey: [u8; 32],
     pub input_amount: u128,
     pub input_nonce: u128,
     pub output_amount: u128,
     pub output_nonce: u128,
+    // Only a nonce rotation: the output amount must equal the input amount
+    pub require_equal: bool,
+}
+
+#[cfg(feature = "zeroize")]
+impl Zeroize for Witness {
+    fn zeroize(&mut self) {
+        self.from_pubkey.zeroize();
+        self.to_pubkey.zeroize();
+        self.input_amount.zeroize();
+        self.input_nonce.zeroize();
+        self.output_amount.zeroize();
+        self.output_nonce.zeroize();
+        self.require_equal.zeroize();
+    }
+}
+
+// Clears the secrets when the witness goes out of scope, including the one
+// `generate_proof` takes by value
+#[cfg(feature = "zeroize")]
+impl Drop for Witness {
+    fn drop(&mut self) {
+        self.zeroize();
+    }
 }
 
 pub struct Proof(groth16::Proof<Bls12>);
@@ -126,23 +259,187 @@ impl Proof {
     }
 }
 
-pub fn generate_proof(witness: Witness, params: &Vec<u8>) -> Proof {
+#[derive(Debug)]
+pub enum ProveError {
+    Params(std::io::Error),
+    Synthesis(SynthesisError),
+}
+
+pub fn generate_proof(witness: Witness, params: &[u8]) -> Result<Proof, ProveError> {
+    prove(witness, params, &mut OsRng)
+}
+
+// Same seed, witness and params always give the same proof bytes. Only for
+// tests, a proof whose randomness is known isn't zero knowledge.
+pub fn generate_proof_seeded(
+    witness: Witness,
+    params: &[u8],
+    seed: [u8; 32],
+) -> Result<Proof, ProveError> {
+    prove(witness, params, &mut ChaCha20Rng::from_seed(seed))
+}
+
+fn prove<R: RngCore>(witness: Witness, params: &[u8], rng: &mut R) -> Result<Proof, ProveError> {
     let c = Twin {
+        from_pubkey: witness.from_pubkey,
+        to_pubkey: witness.to_pubkey,
         input_amount: witness.input_amount,
         input_nonce: witness.input_nonce,
         output_amount: witness.output_amount,
         output_nonce: witness.output_nonce,
+        require_equal: witness.require_equal,
     };
 
-    let params = Params::from_bytes(params.as_ref()).expect("read params");
-    let proof = groth16::create_random_proof(c, &params.0, &mut OsRng).expect("create proof");
+    let params = Params::from_bytes(params).map_err(ProveError::Params)?;
+    let proof = groth16::create_random_proof(c, &params.0, rng).map_err(ProveError::Synthesis)?;
+
+    Ok(Proof(proof))
+}
+
+#[derive(Debug)]
+pub enum VerifyError {
+    VerifyingKey(std::io::Error),
+    Proof(std::io::Error),
+    Verification(SynthesisError),
+}
+
+// The only core failure is a public input count the key doesn't expect, which
+// bellman reports the same way
+impl From<verify_core::VerifyCoreError> for VerifyError {
+    fn from(err: verify_core::VerifyCoreError) -> Self {
+        match err {
+            verify_core::VerifyCoreError::MalformedVerifyingKey => {
+                VerifyError::Verification(SynthesisError::MalformedVerifyingKey)
+            }
+        }
+    }
+}
+
+// Single error type for callers that handle proving and verification failures
+// the same way. `ProveError` and `VerifyError` convert into it with `?`.
+#[derive(Debug)]
+pub enum Zk42Error {
+    Io(std::io::Error),
+    Synthesis(SynthesisError),
+}
+
+impl std::fmt::Display for Zk42Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Zk42Error::Io(err) => write!(f, "malformed bytes: {}", err),
+            Zk42Error::Synthesis(err) => write!(f, "synthesis failed: {}", err),
+        }
+    }
+}
+
+impl From<std::io::Error> for Zk42Error {
+    fn from(err: std::io::Error) -> Self {
+        Zk42Error::Io(err)
+    }
+}
+
+impl From<SynthesisError> for Zk42Error {
+    fn from(err: SynthesisError) -> Self {
+        Zk42Error::Synthesis(err)
+    }
+}
+
+impl From<VerifyError> for Zk42Error {
+    fn from(err: VerifyError) -> Self {
+        match err {
+            VerifyError::VerifyingKey(err) | VerifyError::Proof(err) => Zk42Error::Io(err),
+            VerifyError::Verification(err) => Zk42Error::Synthesis(err),
+        }
+    }
+}
+
+impl std::error::Error for Zk42Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Zk42Error::Io(err) => Some(err),
+            Zk42Error::Synthesis(err) => Some(err),
+        }
+    }
+}
 
-    Proof(proof)
+impl From<ProveError> for Zk42Error {
+    fn from(err: ProveError) -> Self {
+        match err {
+            ProveError::Params(err) => Zk42Error::Io(err),
+            ProveError::Synthesis(err) => Zk42Error::Synthesis(err),
+        }
+    }
 }
 
+// Note hashes of sha256(domain + pubkey + amount + nonce) for the sender and
+// receiver, and whether the proof must move the amount unchanged
 pub struct Input {
     pub from_hash: Vec<u8>,
     pub to_hash: Vec<u8>,
+    pub require_equal: bool,
+}
+
+impl Input {
+    // For proofs made without `Witness::require_equal`
+    pub fn from_hex(from_hash: &str, to_hash: &str) -> Result<Input, hex::FromHexError> {
+        Ok(Input {
+            from_hash: hex::decode(from_hash)?,
+            to_hash: hex::decode(to_hash)?,
+            require_equal: false,
+        })
+    }
+
+    // Packed field elements in circuit order, for `verify_core::verify`
+    pub fn public_inputs(&self) -> Vec<Fr> {
+        let mut combined_hash = self.from_hash.clone();
+        combined_hash.extend(&self.to_hash);
+
+        let hash_bits = multipack::bytes_to_bits(&combined_hash);
+        let mut inputs = multipack::compute_multipacking::<Bls12>(&hash_bits);
+        inputs.extend(multipack::compute_multipacking::<Bls12>(&[
+            self.require_equal
+        ]));
+        inputs
+    }
+
+    // None unless both hashes are 32 bytes long
+    pub fn hashes(&self) -> Option<([u8; 32], [u8; 32])> {
+        if self.from_hash.len() != 32 || self.to_hash.len() != 32 {
+            return None;
+        }
+
+        let mut from_hash = [0u8; 32];
+        let mut to_hash = [0u8; 32];
+        from_hash.copy_from_slice(&self.from_hash);
+        to_hash.copy_from_slice(&self.to_hash);
+        Some((from_hash, to_hash))
+    }
+}
+
+impl From<([u8; 32], [u8; 32])> for Input {
+    fn from((from_hash, to_hash): ([u8; 32], [u8; 32])) -> Self {
+        Input {
+            from_hash: from_hash.to_vec(),
+            to_hash: to_hash.to_vec(),
+            require_equal: false,
+        }
+    }
+}
+
+pub fn compute_input(witness: &Witness) -> Input {
+    Input {
+        from_hash: note_hash(
+            &witness.from_pubkey,
+            witness.input_amount,
+            witness.input_nonce,
+        ),
+        to_hash: note_hash(
+            &witness.to_pubkey,
+            witness.output_amount,
+            witness.output_nonce,
+        ),
+        require_equal: witness.require_equal,
+    }
 }
 
 struct VerifyingKey(groth16::VerifyingKey<Bls12>);
@@ -154,37 +451,131 @@ impl VerifyingKey {
     }
 }
 
-pub fn verify(vk_bytes: &Vec<u8>, proof: &Vec<u8>, input: Input) -> bool {
-    let Input { from_hash, to_hash } = input;
-    let mut combined_hash = from_hash.clone();
-    combined_hash.extend(to_hash);
+fn prepare_verifying_key(
+    vk_bytes: &[u8],
+) -> Result<groth16::PreparedVerifyingKey<Bls12>, VerifyError> {
+    let verifying_key = VerifyingKey::from_bytes(vk_bytes).map_err(VerifyError::VerifyingKey)?;
 
-    let verifying_key = VerifyingKey::from_bytes(vk_bytes).expect("read verifying key");
-    let verifying_key = groth16::prepare_verifying_key(&verifying_key.0);
+    Ok(verify_core::prepare(&verifying_key.0))
+}
 
-    let hash_bits = multipack::bytes_to_bits(&combined_hash);
-    let inputs = multipack::compute_multipacking::<Bls12>(&hash_bits);
+fn verify_prepared(
+    verifying_key: &groth16::PreparedVerifyingKey<Bls12>,
+    proof: &[u8],
+    input: Input,
+) -> Result<bool, VerifyError> {
+    let inputs = input.public_inputs();
+    let proof = Proof::from_bytes(proof).map_err(VerifyError::Proof)?;
 
-    let proof = Proof::from_bytes(proof.as_ref()).expect("read proof");
+    Ok(verify_core::verify(verifying_key, &proof.0, &inputs)?)
+}
 
-    groth16::verify_proof::<Bls12>(&verifying_key, &proof.0, &inputs).expect("verify proof")
+pub fn verify(vk_bytes: &[u8], proof: &[u8], input: Input) -> Result<bool, VerifyError> {
+    verify_prepared(&prepare_verifying_key(vk_bytes)?, proof, input)
+}
+
+// Prepares the verifying key once, then verifies each proof only as the
+// returned iterator is advanced. A bad proof fails its own item, but a bad
+// key fails before any proof is read.
+pub fn verify_stream(
+    vk_bytes: &[u8],
+    items: impl Iterator<Item = (Vec<u8>, Input)>,
+) -> Result<impl Iterator<Item = Result<bool, VerifyError>>, VerifyError> {
+    let verifying_key = prepare_verifying_key(vk_bytes)?;
+
+    Ok(items.map(move |(proof, input)| verify_prepared(&verifying_key, &proof, input)))
+}
+
+mod hex_bytes {
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(bytes: &Vec<u8>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hex::encode(bytes))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+        let hex_str = String::deserialize(deserializer)?;
+        hex::decode(hex_str).map_err(D::Error::custom)
+    }
+}
+
+// A proof bundled with the note hashes it was made for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TwinRecord {
+    #[serde(with = "hex_bytes")]
+    pub proof: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub from_hash: Vec<u8>,
+    #[serde(with = "hex_bytes")]
+    pub to_hash: Vec<u8>,
+    #[serde(default)]
+    pub require_equal: bool,
+}
+
+pub fn prove_twin_record(witness: Witness, params: &[u8]) -> Result<TwinRecord, ProveError> {
+    let Input {
+        from_hash,
+        to_hash,
+        require_equal,
+    } = compute_input(&witness);
+    let proof = generate_proof(witness, params)?;
+
+    Ok(TwinRecord {
+        proof: proof.to_bytes(),
+        from_hash,
+        to_hash,
+        require_equal,
+    })
+}
+
+pub fn verify_twin_record(vk_bytes: &[u8], record: &TwinRecord) -> bool {
+    let input = Input {
+        from_hash: record.from_hash.clone(),
+        to_hash: record.to_hash.clone(),
+        require_equal: record.require_equal,
+    };
+
+    verify(vk_bytes, &record.proof, input).unwrap_or(false)
+}
+
+// Hex decoding layer behind `verify_twin`, kept separate so it can be tested
+// without a wasm target. Any decode or verify error counts as a failure.
+#[cfg(any(feature = "wasm", test))]
+fn verify_hex(vk_hex: &str, proof_hex: &str, from_hash_hex: &str, to_hash_hex: &str) -> bool {
+    let decoded = (
+        hex::decode(vk_hex),
+        hex::decode(proof_hex),
+        Input::from_hex(from_hash_hex, to_hash_hex),
+    );
+
+    match decoded {
+        (Ok(vk), Ok(proof), Ok(input)) => verify(&vk, &proof, input).unwrap_or(false),
+        _ => false,
+    }
+}
+
+#[cfg(feature = "wasm")]
+#[wasm_bindgen]
+pub fn verify_twin(vk_hex: &str, proof_hex: &str, from_hash_hex: &str, to_hash_hex: &str) -> bool {
+    verify_hex(vk_hex, proof_hex, from_hash_hex, to_hash_hex)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use sha2::{Digest, Sha256};
+    const ALICE: [u8; 32] = [1u8; 32];
+    const BOB: [u8; 32] = [2u8; 32];
 
-    fn combine(amount: u128, nonce: u128) -> [u8; 32] {
+    fn combine(pubkey: [u8; 32], amount: u128, nonce: u128) -> [u8; 80] {
         let amount_bytes = amount.to_be_bytes();
         let nonce_bytes = nonce.to_be_bytes();
 
-        let mut bytes = [0u8; 32];
-        for i in 0..16 {
-            bytes[i] = amount_bytes[i];
-            bytes[i + 16] = nonce_bytes[i];
-        }
+        let mut bytes = [0u8; 80];
+        bytes[..16].copy_from_slice(b"twin/note/sha256");
+        bytes[16..48].copy_from_slice(&pubkey);
+        bytes[48..64].copy_from_slice(&amount_bytes);
+        bytes[64..].copy_from_slice(&nonce_bytes);
 
         bytes
     }
@@ -193,22 +584,510 @@ mod tests {
     fn basic_test() {
         let params = trust_setup();
         let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
             input_amount: 2,
             input_nonce: 10,
             output_amount: 2,
             output_nonce: 20,
+            require_equal: false,
         };
 
-        let proof = generate_proof(witness, &params.to_bytes());
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
 
-        let input_hash = Sha256::digest(&combine(2, 10)).to_vec();
-        let output_hash = Sha256::digest(&combine(2, 20)).to_vec();
+        let input_hash = Sha256::digest(&combine(ALICE, 2, 10)[..]).to_vec();
+        let output_hash = Sha256::digest(&combine(BOB, 2, 20)[..]).to_vec();
 
         let input = Input {
             from_hash: input_hash,
             to_hash: output_hash,
+            require_equal: false,
         };
 
-        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input))
+        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input).expect("verify"))
+    }
+
+    #[test]
+    fn verify_rejects_malformed_bytes() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 1,
+            input_nonce: 1,
+            output_amount: 1,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let vk = params.verifying_key();
+        let proof = generate_proof(witness, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let input = || Input {
+            from_hash: vec![0u8; 32],
+            to_hash: vec![0u8; 32],
+            require_equal: false,
+        };
+
+        match verify(&vk[..vk.len() / 2], &proof, input()) {
+            Err(VerifyError::VerifyingKey(_)) => (),
+            _ => panic!("expected verifying key error"),
+        }
+
+        match verify(&vk, &proof[..proof.len() - 1], input()) {
+            Err(VerifyError::Proof(_)) => (),
+            _ => panic!("expected proof error"),
+        }
+    }
+
+    #[test]
+    fn seeded_proof_is_reproducible() {
+        let params = trust_setup_from_seed([42u8; 32]).to_bytes();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 5,
+            input_nonce: 1,
+            output_amount: 5,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let proof = generate_proof_seeded(witness.clone(), &params, [7u8; 32])
+            .expect("prove")
+            .to_bytes();
+        let again = generate_proof_seeded(witness.clone(), &params, [7u8; 32])
+            .expect("prove")
+            .to_bytes();
+        assert_eq!(proof, again);
+
+        let other_seed = generate_proof_seeded(witness, &params, [8u8; 32])
+            .expect("prove")
+            .to_bytes();
+        assert_ne!(proof, other_seed);
+    }
+
+    #[test]
+    fn seeded_trust_setup_is_reproducible() {
+        let params = trust_setup_from_seed([42u8; 32]);
+        assert_eq!(
+            params.to_bytes(),
+            trust_setup_from_seed([42u8; 32]).to_bytes()
+        );
+        assert_ne!(
+            params.to_bytes(),
+            trust_setup_from_seed([43u8; 32]).to_bytes()
+        );
+    }
+
+    #[test]
+    fn twin_record_json_round_trip() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 5,
+            input_nonce: 1,
+            output_amount: 4,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let record = prove_twin_record(witness, &params.to_bytes()).expect("prove");
+        let json = serde_json::to_string(&record).expect("serialize");
+        let decoded: TwinRecord = serde_json::from_str(&json).expect("deserialize");
+        assert_eq!(decoded, record);
+
+        assert!(verify_twin_record(&params.verifying_key(), &decoded));
+
+        let tampered = TwinRecord {
+            to_hash: note_hash(&BOB, 5, 2),
+            ..decoded
+        };
+        assert!(!verify_twin_record(&params.verifying_key(), &tampered));
+    }
+
+    #[test]
+    fn generate_proof_rejects_truncated_params() {
+        let params = trust_setup().to_bytes();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 1,
+            input_nonce: 1,
+            output_amount: 1,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        match generate_proof(witness, &params[..params.len() / 2]) {
+            Err(ProveError::Params(_)) => (),
+            _ => panic!("expected params error"),
+        }
+    }
+
+    #[test]
+    fn verify_rejects_swapped_receiver() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 3,
+            input_nonce: 1,
+            output_amount: 3,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let proof = generate_proof(witness, &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        let input = |to_pubkey| Input {
+            from_hash: note_hash(&ALICE, 3, 1),
+            to_hash: note_hash(&to_pubkey, 3, 2),
+            require_equal: false,
+        };
+
+        let vk = params.verifying_key();
+        assert!(verify(&vk, &proof, input(BOB)).expect("verify"));
+        assert!(!verify(&vk, &proof, input([3u8; 32])).expect("verify"));
+    }
+
+    #[test]
+    fn verify_hex_matches_native_encoding() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 4,
+            input_nonce: 1,
+            output_amount: 4,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let record = prove_twin_record(witness, &params.to_bytes()).expect("prove");
+        let vk_hex = hex::encode(params.verifying_key());
+        let proof_hex = hex::encode(&record.proof);
+        let from_hex = hex::encode(&record.from_hash);
+        let to_hex = hex::encode(&record.to_hash);
+
+        assert!(verify_hex(&vk_hex, &proof_hex, &from_hex, &to_hex));
+        assert!(!verify_hex(&vk_hex, &proof_hex, &to_hex, &from_hex));
+        assert!(!verify_hex(&vk_hex, "not hex", &from_hex, &to_hex));
+        assert!(!verify_hex(&vk_hex[..10], &proof_hex, &from_hex, &to_hex));
+    }
+
+    #[test]
+    fn params_save_and_load() {
+        let path = std::env::temp_dir().join("twin-params-save-and-load.params");
+        let params = trust_setup();
+        params.save(&path).expect("save");
+
+        let loaded = Params::<Bls12>::load(&path).expect("load");
+        std::fs::remove_file(&path).expect("remove");
+        assert_eq!(loaded.to_bytes(), params.to_bytes());
+
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 4,
+            input_nonce: 1,
+            output_amount: 4,
+            output_nonce: 2,
+            require_equal: false,
+        };
+        let record = prove_twin_record(witness, &loaded.to_bytes()).expect("prove");
+        assert!(verify_twin_record(&params.verifying_key(), &record));
+    }
+
+    #[test]
+    fn verifying_key_from_params_bytes_matches_params() {
+        let params = trust_setup();
+        let params_bytes = params.to_bytes();
+
+        let vk = verifying_key_from_params_bytes(&params_bytes).expect("extract vk");
+        assert_eq!(vk, params.verifying_key());
+        assert!(verifying_key_from_params_bytes(&params_bytes[..vk.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn verify_with_computed_input() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 3,
+            input_nonce: 7,
+            output_amount: 3,
+            output_nonce: 8,
+            require_equal: false,
+        };
+
+        let input = compute_input(&witness);
+        assert_eq!(
+            input.from_hash,
+            Sha256::digest(&combine(ALICE, 3, 7)[..]).to_vec()
+        );
+        assert_eq!(
+            input.to_hash,
+            Sha256::digest(&combine(BOB, 3, 8)[..]).to_vec()
+        );
+
+        let proof = generate_proof(witness, &params.to_bytes()).expect("prove");
+        assert!(verify(&params.verifying_key(), &proof.to_bytes(), input).expect("verify"));
+    }
+
+    #[test]
+    fn note_hash_differs_from_mixer_note_hash() {
+        let (amount, nonce) = (5u128, 1u128);
+        for pubkey in [ALICE, BOB, [0u8; 32]].iter() {
+            // The same body under no domain and under the mixer's
+            let mut body = pubkey.to_vec();
+            body.extend(&amount.to_be_bytes());
+            body.extend(&nonce.to_be_bytes());
+            let mut mixer_preimage = gadgets::MIXER_NOTE_DOMAIN.to_vec();
+            mixer_preimage.extend(&body);
+
+            let hash = note_hash(pubkey, amount, nonce);
+            assert_ne!(hash, Sha256::digest(&body).to_vec());
+            assert_ne!(hash, Sha256::digest(&mixer_preimage).to_vec());
+        }
+    }
+
+    #[test]
+    fn input_from_hex() {
+        let input =
+            Input::from_hex(&hex::encode([1u8; 32]), &hex::encode([2u8; 32])).expect("valid hex");
+        assert_eq!(input.from_hash, vec![1u8; 32]);
+        assert_eq!(input.to_hash, vec![2u8; 32]);
+
+        assert!(Input::from_hex("zz", &hex::encode([2u8; 32])).is_err());
+        assert!(Input::from_hex(&hex::encode([1u8; 32]), "abc").is_err());
+    }
+
+    #[test]
+    fn verify_stream_checks_each_item() {
+        let params = trust_setup();
+        let witness = |nonce| Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 3,
+            input_nonce: nonce,
+            output_amount: 3,
+            output_nonce: nonce + 1,
+            require_equal: false,
+        };
+        let item = |nonce| {
+            let proof = generate_proof(witness(nonce), &params.to_bytes())
+                .expect("prove")
+                .to_bytes();
+            (proof, compute_input(&witness(nonce)))
+        };
+
+        let (proof, _) = item(5);
+        let items = vec![item(1), (proof, compute_input(&witness(7))), item(3)];
+
+        let vk = params.verifying_key();
+        let results = verify_stream(&vk, items.into_iter())
+            .expect("valid key")
+            .map(|result| result.expect("verify"))
+            .collect::<Vec<_>>();
+        assert_eq!(results, vec![true, false, true]);
+
+        assert!(verify_stream(&vk[..vk.len() / 2], std::iter::empty()).is_err());
+    }
+
+    #[test]
+    fn core_verify_takes_parsed_values() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 3,
+            input_nonce: 1,
+            output_amount: 3,
+            output_nonce: 2,
+            require_equal: false,
+        };
+        let proof = generate_proof(witness.clone(), &params.to_bytes()).expect("prove");
+        let vk = VerifyingKey::from_bytes(&params.verifying_key()).expect("vk");
+        let vk = verify_core::prepare(&vk.0);
+
+        let inputs = compute_input(&witness).public_inputs();
+        assert!(verify_core::verify(&vk, &proof.0, &inputs).expect("verify"));
+
+        let other = Witness {
+            output_nonce: 3,
+            ..witness
+        };
+        let inputs = compute_input(&other).public_inputs();
+        assert!(!verify_core::verify(&vk, &proof.0, &inputs).expect("verify"));
+    }
+
+    #[test]
+    fn input_from_hash_arrays() {
+        let input = Input::from(([1u8; 32], [2u8; 32]));
+        assert_eq!(input.hashes(), Some(([1u8; 32], [2u8; 32])));
+
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 1,
+            input_nonce: 1,
+            output_amount: 1,
+            output_nonce: 2,
+            require_equal: false,
+        };
+        let (from_hash, to_hash) = compute_input(&witness).hashes().expect("32-byte hashes");
+        assert_eq!(from_hash.to_vec(), compute_input(&witness).from_hash);
+        assert_eq!(to_hash.to_vec(), compute_input(&witness).to_hash);
+
+        let short = Input {
+            from_hash: vec![1u8; 31],
+            to_hash: vec![2u8; 32],
+            require_equal: false,
+        };
+        assert_eq!(short.hashes(), None);
+        let long = Input {
+            from_hash: vec![1u8; 32],
+            to_hash: vec![2u8; 33],
+            require_equal: false,
+        };
+        assert_eq!(long.hashes(), None);
+    }
+
+    #[test]
+    fn errors_convert_into_zk42_error() {
+        let witness = |output_amount| Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 4,
+            input_nonce: 1,
+            output_amount,
+            output_nonce: 2,
+            require_equal: false,
+        };
+        let prove = |witness, params: &[u8]| -> Result<Proof, Zk42Error> {
+            Ok(generate_proof(witness, params)?)
+        };
+
+        match prove(witness(4), &[0u8; 4]) {
+            Err(err @ Zk42Error::Io(_)) => assert!(err.to_string().starts_with("malformed bytes")),
+            _ => panic!("expected io error"),
+        }
+        match prove(witness(5), &trust_setup().to_bytes()) {
+            Err(Zk42Error::Synthesis(SynthesisError::Unsatisfiable)) => (),
+            _ => panic!("expected synthesis error"),
+        }
+
+        let err = std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "short");
+        match Zk42Error::from(VerifyError::Proof(err)) {
+            Zk42Error::Io(_) => (),
+            _ => panic!("expected io error"),
+        }
+        match Zk42Error::from(VerifyError::Verification(SynthesisError::Unsatisfiable)) {
+            Zk42Error::Synthesis(_) => (),
+            _ => panic!("expected synthesis error"),
+        }
+    }
+
+    #[test]
+    fn require_equal_rejects_changed_amount() {
+        let params = trust_setup();
+        let witness = |output_amount, require_equal| Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 5,
+            input_nonce: 1,
+            output_amount,
+            output_nonce: 2,
+            require_equal,
+        };
+
+        let vk = params.verifying_key();
+        let proof = generate_proof(witness(4, false), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        assert!(verify(&vk, &proof, compute_input(&witness(4, false))).expect("verify"));
+
+        match generate_proof(witness(4, true), &params.to_bytes()) {
+            Err(ProveError::Synthesis(SynthesisError::Unsatisfiable)) => (),
+            _ => panic!("expected unequal amounts to be rejected"),
+        }
+
+        let proof = generate_proof(witness(5, true), &params.to_bytes())
+            .expect("prove")
+            .to_bytes();
+        assert!(verify(&vk, &proof, compute_input(&witness(5, true))).expect("verify"));
+        // The flag is a public input, so a verifier can't drop it
+        assert!(!verify(&vk, &proof, compute_input(&witness(5, false))).expect("verify"));
+    }
+
+    #[cfg(feature = "zeroize")]
+    #[test]
+    fn zeroize_clears_witness() {
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 5,
+            input_nonce: 1,
+            output_amount: 5,
+            output_nonce: 2,
+            require_equal: true,
+        };
+        let cleared = |witness: &Witness| {
+            assert_eq!(
+                (witness.from_pubkey, witness.to_pubkey),
+                ([0u8; 32], [0u8; 32])
+            );
+            assert_eq!(
+                (
+                    witness.input_amount,
+                    witness.input_nonce,
+                    witness.output_amount,
+                    witness.output_nonce
+                ),
+                (0, 0, 0, 0)
+            );
+            assert!(!witness.require_equal);
+        };
+
+        let mut zeroized = witness.clone();
+        zeroized.zeroize();
+        cleared(&zeroized);
+
+        // Dropping runs the same clearing. The fields are plain integers, so
+        // reading them after the drop only sees what it left behind.
+        let mut dropped = std::mem::ManuallyDrop::new(witness);
+        unsafe { std::mem::ManuallyDrop::drop(&mut dropped) };
+        cleared(&dropped);
+    }
+
+    #[test]
+    fn verify_accepts_borrowed_slices() {
+        let params = trust_setup();
+        let witness = Witness {
+            from_pubkey: ALICE,
+            to_pubkey: BOB,
+            input_amount: 4,
+            input_nonce: 1,
+            output_amount: 4,
+            output_nonce: 2,
+            require_equal: false,
+        };
+
+        let params_bytes: Box<[u8]> = params.to_bytes().into_boxed_slice();
+        let mut proof = [0u8; 192];
+        proof.copy_from_slice(
+            &generate_proof(witness.clone(), &params_bytes)
+                .expect("prove")
+                .to_bytes(),
+        );
+        let vk: Box<[u8]> = params.verifying_key().into_boxed_slice();
+
+        assert!(Proof::from_bytes(&proof[..]).is_ok());
+        assert!(verify(&vk, &proof, compute_input(&witness)).expect("verify"));
     }
 }
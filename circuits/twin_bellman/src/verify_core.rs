@@ -0,0 +1,27 @@
+// The groth16 check on an already decoded key, proof and public inputs. It
+// reads no bytes, draws no randomness and allocates nothing beyond what the
+// pairing does. The file only uses `core`, and tests/verify_core_no_std.rs
+// builds it as a `#![no_std]` crate to keep it that way. bellman 0.6 and
+// pairing 0.16 still link std, so a no_std target also needs builds of them
+// without it.
+use bellman::groth16;
+use pairing::bls12_381::{Bls12, Fr};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VerifyCoreError {
+    // The public inputs don't match the key's IC points in number
+    MalformedVerifyingKey,
+}
+
+pub fn prepare(vk: &groth16::VerifyingKey<Bls12>) -> groth16::PreparedVerifyingKey<Bls12> {
+    groth16::prepare_verifying_key(vk)
+}
+
+pub fn verify(
+    vk: &groth16::PreparedVerifyingKey<Bls12>,
+    proof: &groth16::Proof<Bls12>,
+    inputs: &[Fr],
+) -> Result<bool, VerifyCoreError> {
+    // `MalformedVerifyingKey` is the only error verify_proof returns
+    groth16::verify_proof(vk, proof, inputs).map_err(|_| VerifyCoreError::MalformedVerifyingKey)
+}